@@ -0,0 +1,1040 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::{Method, RequestBuilder, StatusCode, Url, header};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+pub const API_URL: &str = "https://api.github.com";
+
+/// Media type that wraps each starred repo with the time it was starred.
+const STAR_MEDIA_TYPE: &str = "application/vnd.github.star+json";
+const JSON_MEDIA_TYPE: &str = "application/vnd.github+json";
+
+/// Pages kept for conditional requests. At GitHub's default 30 items per
+/// page this covers the first 1500 stars, gists or watched repos, and bounds
+/// what a long-running daemon keeps in memory.
+const PAGE_CACHE_SIZE: usize = 50;
+
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+/// Used when a rate limited response says neither when to retry nor when the
+/// limit resets.
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+/// Longer waits fail the request instead, the next scheduled run picks up
+/// from the checkpoint.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StarredRepo {
+    pub starred_at: String,
+    pub repo: Repo,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Repo {
+    #[serde(default)]
+    pub full_name: String,
+    #[serde(default)]
+    pub html_url: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Gist {
+    pub html_url: String,
+    pub description: Option<String>,
+    /// In the order GitHub returns them, which is the order gist.github.com
+    /// shows them in.
+    #[serde(default, deserialize_with = "deserialize_gist_files")]
+    pub files: Vec<GistFile>,
+    pub owner: Option<User>,
+}
+
+#[derive(Debug)]
+pub struct GistFile {
+    pub filename: String,
+    pub language: Option<String>,
+}
+
+/// GitHub returns gist files as an object keyed by file name. A map type
+/// would reorder them, so read the entries one by one instead.
+fn deserialize_gist_files<'de, D>(deserializer: D) -> Result<Vec<GistFile>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Entry {
+        language: Option<String>,
+    }
+
+    struct FilesVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for FilesVisitor {
+        type Value = Vec<GistFile>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of gist files")
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut files = Vec::new();
+            while let Some((filename, entry)) = map.next_entry::<String, Entry>()? {
+                files.push(GistFile {
+                    filename,
+                    language: entry.language,
+                });
+            }
+            Ok(files)
+        }
+    }
+
+    deserializer.deserialize_map(FilesVisitor)
+}
+
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Query string of the next page, e.g. `?page=2`.
+    pub next: Option<String>,
+}
+
+/// A user's named list of starred repos.
+#[derive(Debug)]
+pub struct StarList {
+    pub name: String,
+    pub repos: Vec<Repo>,
+}
+
+const STAR_LISTS_QUERY: &str = r#"
+query($after: String) {
+  viewer {
+    lists(first: 100, after: $after) {
+      pageInfo { hasNextPage endCursor }
+      nodes {
+        id
+        name
+        items(first: 100) { ...ListItems }
+      }
+    }
+  }
+}
+"#;
+
+const STAR_LIST_ITEMS_QUERY: &str = r#"
+query($id: ID!, $after: String) {
+  node(id: $id) {
+    ... on UserList {
+      items(first: 100, after: $after) { ...ListItems }
+    }
+  }
+}
+"#;
+
+const LIST_ITEMS_FRAGMENT: &str = r#"
+fragment ListItems on UserListItemsConnection {
+  pageInfo { hasNextPage endCursor }
+  nodes {
+    ... on Repository {
+      nameWithOwner
+      url
+      description
+      primaryLanguage { name }
+      repositoryTopics(first: 20) { nodes { topic { name } } }
+    }
+  }
+}
+"#;
+
+#[derive(Debug, Deserialize)]
+struct GraphQlResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<ErrorBody>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Nodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ViewerData {
+    viewer: Viewer,
+}
+
+#[derive(Debug, Deserialize)]
+struct Viewer {
+    lists: UserLists,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserLists {
+    page_info: PageInfo,
+    nodes: Vec<UserList>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserList {
+    id: String,
+    name: String,
+    items: ListItems,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeData {
+    node: Option<ListItemsNode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListItemsNode {
+    items: ListItems,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListItems {
+    page_info: PageInfo,
+    nodes: Vec<Option<ListItem>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopicNode {
+    topic: Named,
+}
+
+/// A list item, only repositories have fields, other kinds come back empty.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ListItem {
+    name_with_owner: String,
+    url: String,
+    description: Option<String>,
+    primary_language: Option<Named>,
+    repository_topics: Option<Nodes<TopicNode>>,
+}
+
+impl ListItem {
+    fn into_repo(self) -> Option<Repo> {
+        if self.url.is_empty() {
+            return None;
+        }
+        Some(Repo {
+            full_name: self.name_with_owner,
+            html_url: self.url,
+            description: self.description,
+            topics: self
+                .repository_topics
+                .map(|t| t.nodes.into_iter().map(|n| n.topic.name).collect())
+                .unwrap_or_default(),
+            language: self.primary_language.map(|l| l.name),
+        })
+    }
+}
+
+impl PageInfo {
+    fn next_cursor(self) -> Option<String> {
+        self.has_next_page.then_some(self.end_cursor).flatten()
+    }
+}
+
+impl ListItems {
+    /// Adds the repos to `repos` and returns the cursor of the next page.
+    fn collect_into(self, repos: &mut Vec<Repo>) -> Option<String> {
+        repos.extend(
+            self.nodes
+                .into_iter()
+                .flatten()
+                .filter_map(ListItem::into_repo),
+        );
+        self.page_info.next_cursor()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// A page body GitHub may answer with `304 Not Modified`.
+#[derive(Debug, Clone)]
+struct CachedPage {
+    etag: String,
+    body: String,
+    next: Option<String>,
+}
+
+/// The most recently used pages by URL, evicting the least recently used one
+/// when full.
+struct PageCache {
+    capacity: usize,
+    pages: HashMap<String, (u64, CachedPage)>,
+    tick: u64,
+}
+
+impl PageCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pages: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, url: &str) -> Option<CachedPage> {
+        self.tick += 1;
+        let (used, page) = self.pages.get_mut(url)?;
+        *used = self.tick;
+        Some(page.clone())
+    }
+
+    fn insert(&mut self, url: &str, page: CachedPage) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.pages.contains_key(url) && self.pages.len() >= self.capacity {
+            let oldest = self
+                .pages
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(url, _)| url.clone());
+            if let Some(oldest) = oldest {
+                self.pages.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.pages.insert(url.to_string(), (self.tick, page));
+    }
+}
+
+pub struct GitHubClient {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+    /// Conditional requests for unchanged pages don't count against the rate
+    /// limit, so the last response of recently fetched pages is kept by URL.
+    pages: Mutex<PageCache>,
+}
+
+impl GitHubClient {
+    pub fn new(token: String) -> Self {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            header::HeaderValue::from_static("karakeep-sync/1.0"),
+        );
+        headers.insert(
+            "X-GitHub-Api-Version",
+            header::HeaderValue::from_static("2022-11-28"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap();
+
+        Self {
+            client,
+            base_url: API_URL.to_string(),
+            token,
+            pages: Mutex::new(PageCache::new(PAGE_CACHE_SIZE)),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Keeps up to `pages` pages for conditional requests instead of the
+    /// default, `0` turns the cache off.
+    pub fn with_page_cache_size(mut self, pages: usize) -> Self {
+        self.pages = Mutex::new(PageCache::new(pages));
+        self
+    }
+
+    /// The authenticated user.
+    pub async fn user(&self) -> anyhow::Result<User> {
+        let url = format!("{}/user", self.base_url);
+        let resp = self
+            .send(Method::GET, &url, |req| {
+                req.header(header::ACCEPT, JSON_MEDIA_TYPE)
+            })
+            .await?;
+        Ok(resp.json::<User>().await?)
+    }
+
+    /// One page of the user's starred repos, starting at the first without
+    /// `page`, a query string as returned in [`Page::next`].
+    pub async fn starred(&self, page: Option<&str>) -> anyhow::Result<Page<StarredRepo>> {
+        let url = format!("{}/user/starred{}", self.base_url, page.unwrap_or(""));
+        self.get_page(&url, STAR_MEDIA_TYPE).await
+    }
+
+    /// One page of the gists the user starred.
+    pub async fn starred_gists(&self, page: Option<&str>) -> anyhow::Result<Page<Gist>> {
+        let url = format!("{}/gists/starred{}", self.base_url, page.unwrap_or(""));
+        self.get_page(&url, JSON_MEDIA_TYPE).await
+    }
+
+    /// One page of the repos the user watches.
+    pub async fn subscriptions(&self, page: Option<&str>) -> anyhow::Result<Page<Repo>> {
+        let url = format!("{}/user/subscriptions{}", self.base_url, page.unwrap_or(""));
+        self.get_page(&url, JSON_MEDIA_TYPE).await
+    }
+
+    /// The user's star lists with all their repos.
+    pub async fn star_lists(&self) -> anyhow::Result<Vec<StarList>> {
+        let mut user_lists = Vec::new();
+        let mut after = None;
+        loop {
+            let data: ViewerData = self
+                .graphql(STAR_LISTS_QUERY, serde_json::json!({ "after": after }))
+                .await?;
+            user_lists.extend(data.viewer.lists.nodes);
+            after = data.viewer.lists.page_info.next_cursor();
+            if after.is_none() {
+                break;
+            }
+        }
+
+        let mut lists = Vec::new();
+        for list in user_lists {
+            let mut repos = Vec::new();
+            let mut after = list.items.collect_into(&mut repos);
+            while let Some(cursor) = after {
+                let data: NodeData = self
+                    .graphql(
+                        STAR_LIST_ITEMS_QUERY,
+                        serde_json::json!({ "id": list.id, "after": cursor }),
+                    )
+                    .await?;
+                let node = data
+                    .node
+                    .ok_or_else(|| anyhow::anyhow!("GitHub star list '{}' is gone", list.name))?;
+                after = node.items.collect_into(&mut repos);
+            }
+            lists.push(StarList {
+                name: list.name,
+                repos,
+            });
+        }
+        Ok(lists)
+    }
+
+    /// GitHub Enterprise Server serves the REST API under `/api/v3` and
+    /// GraphQL under `/api/graphql`.
+    fn graphql_url(&self) -> String {
+        let api = self.base_url.strip_suffix("/v3").unwrap_or(&self.base_url);
+        format!("{api}/graphql")
+    }
+
+    /// Runs one of the star list queries, which share the list items fragment.
+    async fn graphql<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> anyhow::Result<T> {
+        let body = serde_json::json!({
+            "query": format!("{query}{LIST_ITEMS_FRAGMENT}"),
+            "variables": variables,
+        });
+        let resp = self
+            .send(Method::POST, &self.graphql_url(), |req| req.json(&body))
+            .await?;
+
+        let resp = resp.json::<GraphQlResponse<T>>().await?;
+        if let Some(error) = resp.errors.first() {
+            anyhow::bail!("GitHub GraphQL query failed: {}", error.message);
+        }
+        resp.data
+            .ok_or_else(|| anyhow::anyhow!("GitHub GraphQL response has no data"))
+    }
+
+    async fn get_page<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        accept: &str,
+    ) -> anyhow::Result<Page<T>> {
+        let cached = self.pages.lock().unwrap().get(url);
+        let resp = self
+            .send(Method::GET, url, |req| {
+                let req = req.header(header::ACCEPT, accept);
+                match &cached {
+                    Some(cached) => req.header(header::IF_NONE_MATCH, &cached.etag),
+                    None => req,
+                }
+            })
+            .await?;
+
+        let page = match cached {
+            Some(cached) if resp.status() == StatusCode::NOT_MODIFIED => {
+                tracing::debug!("GitHub page {} is unchanged", url);
+                cached
+            }
+            // only possible if a proxy answers unconditional requests with 304
+            None if resp.status() == StatusCode::NOT_MODIFIED => {
+                anyhow::bail!("GitHub returned 304 for {url} without a cached copy")
+            }
+            _ => {
+                let etag = resp
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|h| h.to_str().ok())
+                    .map(str::to_string);
+                let next = resp
+                    .headers()
+                    .get(header::LINK)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(parse_next_link);
+                let page = CachedPage {
+                    etag: etag.unwrap_or_default(),
+                    body: resp.text().await?,
+                    next,
+                };
+                if !page.etag.is_empty() {
+                    self.pages.lock().unwrap().insert(url, page.clone());
+                }
+                page
+            }
+        };
+
+        let items = serde_json::from_str::<Vec<T>>(&page.body)
+            .map_err(|e| anyhow::anyhow!("failed to parse GitHub response from {url}: {e}"))?;
+        Ok(Page {
+            items,
+            next: page.next,
+        })
+    }
+
+    /// Sends a request built by `build` on top of the authenticated base
+    /// request, waiting out rate limits. Returns successful and
+    /// `304 Not Modified` responses.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        build: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> anyhow::Result<reqwest::Response> {
+        for attempt in 0..=MAX_RATE_LIMIT_RETRIES {
+            let req = self
+                .client
+                .request(method.clone(), url)
+                .bearer_auth(&self.token);
+            let resp = build(req).send().await?;
+
+            let status = resp.status();
+            if status.is_success() || status == StatusCode::NOT_MODIFIED {
+                return Ok(resp);
+            }
+
+            let Some(wait) = rate_limit_wait(&resp) else {
+                let body = resp.text().await.unwrap_or_default();
+                let message = serde_json::from_str::<ErrorBody>(&body)
+                    .map(|e| e.message)
+                    .unwrap_or(body);
+                anyhow::bail!("GitHub returned {status} for {url}: {message}");
+            };
+            if attempt == MAX_RATE_LIMIT_RETRIES {
+                break;
+            }
+            if wait > MAX_RATE_LIMIT_WAIT {
+                anyhow::bail!("GitHub rate limit exceeded, it resets in {wait:?}");
+            }
+
+            tracing::warn!("GitHub is rate limiting {}, retrying in {:?}", url, wait);
+            tokio::time::sleep(wait).await;
+        }
+
+        anyhow::bail!(
+            "GitHub is still rate limiting {} after {} retries",
+            url,
+            MAX_RATE_LIMIT_RETRIES
+        )
+    }
+}
+
+fn header_secs(resp: &reqwest::Response, name: &str) -> Option<u64> {
+    resp.headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.trim().parse::<u64>().ok())
+}
+
+/// How long to wait before retrying `resp`, or `None` if it isn't rate
+/// limited. GitHub answers both primary and secondary rate limits with either
+/// 403 or 429.
+fn rate_limit_wait(resp: &reqwest::Response) -> Option<Duration> {
+    if !matches!(
+        resp.status(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) {
+        return None;
+    }
+
+    if let Some(secs) = header_secs(resp, header::RETRY_AFTER.as_str()) {
+        return Some(Duration::from_secs(secs));
+    }
+    if header_secs(resp, "x-ratelimit-remaining") == Some(0) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let wait = header_secs(resp, "x-ratelimit-reset")
+            .map(|reset| Duration::from_secs(reset.saturating_sub(now)))
+            .unwrap_or(RATE_LIMIT_BACKOFF);
+        return Some(wait);
+    }
+    // a 429 without any hints is still a rate limit, a plain 403 is not
+    (resp.status() == StatusCode::TOO_MANY_REQUESTS).then_some(RATE_LIMIT_BACKOFF)
+}
+
+pub fn parse_next_link(link_header: &str) -> Option<String> {
+    link_header.split(',').find_map(|link| {
+        let parts: Vec<&str> = link.split(';').collect();
+        if parts.len() == 2 && parts[1].trim() == r#"rel="next""# {
+            let url: Url = parts[0]
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .parse()
+                .ok()?;
+
+            let query = url.query()?;
+            Some(format!("?{query}"))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GitHubClient, parse_next_link};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use wiremock::matchers::{body_partial_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn starred_json(name: &str) -> serde_json::Value {
+        serde_json::json!([{
+            "starred_at": "2024-01-02T03:04:05Z",
+            "repo": {
+                "full_name": name,
+                "html_url": format!("https://github.com/{name}"),
+                "description": null,
+                "language": "Rust"
+            }
+        }])
+    }
+
+    fn client(server: &MockServer) -> GitHubClient {
+        GitHubClient::new("token".to_string()).with_base_url(&server.uri())
+    }
+
+    #[test]
+    fn test_parse_next_link() {
+        let link_header = r#"<https://api.github.com/user/starred?page=2>; rel="next", <https://api.github.com/user/starred?page=34>; rel="last""#;
+        let next_link = parse_next_link(link_header);
+        assert_eq!(next_link, Some("?page=2".to_string()));
+
+        let link_header_no_next = r#"<https://api.github.com/user/starred?page=34>; rel="last""#;
+        let next_link = parse_next_link(link_header_no_next);
+        assert_eq!(next_link, None);
+
+        let empty_link_header = "";
+        let next_link = parse_next_link(empty_link_header);
+        assert_eq!(next_link, None);
+    }
+
+    #[tokio::test]
+    async fn test_starred_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user/starred"))
+            .and(query_param("page", "2"))
+            .and(header("authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(starred_json("b/b")))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/starred"))
+            .and(header("accept", "application/vnd.github.star+json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(starred_json("a/a"))
+                    .insert_header(
+                        "link",
+                        format!(r#"<{}/user/starred?page=2>; rel="next""#, server.uri()),
+                    ),
+            )
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        let first = client.starred(None).await.unwrap();
+        assert_eq!(first.items[0].repo.full_name, "a/a");
+        assert_eq!(first.next.as_deref(), Some("?page=2"));
+
+        let second = client.starred(first.next.as_deref()).await.unwrap();
+        assert_eq!(second.items[0].repo.full_name, "b/b");
+        assert_eq!(second.next, None);
+    }
+
+    #[tokio::test]
+    async fn test_starred_gists_and_subscriptions() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/gists/starred"))
+            .and(header("accept", "application/vnd.github+json"))
+            // a raw body, since `json!` would sort the file names
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"[{
+                    "html_url": "https://gist.github.com/abc",
+                    "description": "Handy script",
+                    "files": {
+                        "run.sh": { "filename": "run.sh", "language": "Shell" },
+                        "README.md": { "filename": "README.md", "language": "Markdown" }
+                    },
+                    "owner": { "login": "octocat" }
+                }]"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/subscriptions"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                    "full_name": "a/a",
+                    "html_url": "https://github.com/a/a",
+                    "description": null,
+                    "topics": ["cli"],
+                    "language": null
+                }])),
+            )
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        let gists = client.starred_gists(None).await.unwrap();
+        let gist = &gists.items[0];
+        assert_eq!(gist.html_url, "https://gist.github.com/abc");
+        assert_eq!(gist.owner.as_ref().unwrap().login, "octocat");
+        assert_eq!(
+            gist.files.iter().map(|f| &f.filename).collect::<Vec<_>>(),
+            vec!["run.sh", "README.md"]
+        );
+
+        let watched = client.subscriptions(None).await.unwrap();
+        assert_eq!(watched.items[0].full_name, "a/a");
+        assert_eq!(watched.next, None);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_page_is_served_from_cache() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user/starred"))
+            .and(header("if-none-match", r#""v1""#))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/starred"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(starred_json("a/a"))
+                    .insert_header("etag", r#""v1""#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = client(&server);
+        client.starred(None).await.unwrap();
+        let page = client.starred(None).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].repo.full_name, "a/a");
+    }
+
+    #[tokio::test]
+    async fn test_evicted_page_is_fetched_again() {
+        let server = MockServer::start().await;
+        for (page, etag) in [("1", r#""v1""#), ("2", r#""v2""#)] {
+            Mock::given(method("GET"))
+                .and(path("/user/starred"))
+                .and(query_param("page", page))
+                .and(header("if-none-match", etag))
+                .respond_with(ResponseTemplate::new(304))
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path("/user/starred"))
+                .and(query_param("page", page))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(starred_json(&format!("{page}/{page}")))
+                        .insert_header("etag", etag),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let client = client(&server).with_page_cache_size(1);
+        client.starred(Some("?page=1")).await.unwrap();
+        // evicts page 1
+        client.starred(Some("?page=2")).await.unwrap();
+        let page = client.starred(Some("?page=1")).await.unwrap();
+        assert_eq!(page.items[0].repo.full_name, "1/1");
+        // page 2 is the one evicted now, a 304 for page 1 is still served
+        let page = client.starred(Some("?page=1")).await.unwrap();
+        assert_eq!(page.items[0].repo.full_name, "1/1");
+
+        let requests = server.received_requests().await.unwrap();
+        let conditional = requests
+            .iter()
+            .map(|r| r.headers.contains_key("if-none-match"))
+            .collect::<Vec<_>>();
+        assert_eq!(conditional, vec![false, false, false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limited_requests() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .insert_header("x-ratelimit-remaining", "0")
+                    .insert_header("x-ratelimit-reset", now.to_string()),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "login": "octocat"
+            })))
+            .mount(&server)
+            .await;
+
+        let user = client(&server).user().await.unwrap();
+        assert_eq!(user.login, "octocat");
+    }
+
+    fn list_item_json(name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "nameWithOwner": name,
+            "url": format!("https://github.com/{name}"),
+            "description": null,
+            "primaryLanguage": { "name": "Rust" },
+            "repositoryTopics": { "nodes": [{ "topic": { "name": "cli" } }] }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_star_lists() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/graphql"))
+            .and(body_partial_json(serde_json::json!({
+                "variables": { "id": "L1", "after": "c1" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "node": { "items": {
+                    "pageInfo": { "hasNextPage": false, "endCursor": "c2" },
+                    "nodes": [list_item_json("b/b")]
+                } } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/graphql"))
+            .and(header("authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "viewer": { "lists": {
+                    "pageInfo": { "hasNextPage": false, "endCursor": "l2" },
+                    "nodes": [
+                    {
+                        "id": "L1",
+                        "name": "Tools",
+                        "items": {
+                            "pageInfo": { "hasNextPage": true, "endCursor": "c1" },
+                            "nodes": [list_item_json("a/a"), {}]
+                        }
+                    },
+                    {
+                        "id": "L2",
+                        "name": "Empty",
+                        "items": {
+                            "pageInfo": { "hasNextPage": false, "endCursor": null },
+                            "nodes": []
+                        }
+                    }
+                ] } } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // GitHub Enterprise Server layout
+        let lists = GitHubClient::new("token".to_string())
+            .with_base_url(&format!("{}/api/v3", server.uri()))
+            .star_lists()
+            .await
+            .unwrap();
+        assert_eq!(lists.len(), 2);
+        assert_eq!(lists[0].name, "Tools");
+        let names = lists[0]
+            .repos
+            .iter()
+            .map(|r| r.full_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a/a", "b/b"]);
+        assert_eq!(lists[0].repos[0].html_url, "https://github.com/a/a");
+        assert_eq!(lists[0].repos[0].topics, vec!["cli"]);
+        assert_eq!(lists[0].repos[0].language.as_deref(), Some("Rust"));
+        assert!(lists[1].repos.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_star_lists_are_paged() {
+        fn list_json(id: &str, repo: &str) -> serde_json::Value {
+            serde_json::json!({
+                "id": id,
+                "name": id,
+                "items": {
+                    "pageInfo": { "hasNextPage": false, "endCursor": null },
+                    "nodes": [list_item_json(repo)]
+                }
+            })
+        }
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .and(body_partial_json(serde_json::json!({
+                "variables": { "after": "p1" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "viewer": { "lists": {
+                    "pageInfo": { "hasNextPage": false, "endCursor": "p2" },
+                    "nodes": [list_json("L101", "b/b")]
+                } } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .and(body_partial_json(serde_json::json!({
+                "variables": { "after": null }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "viewer": { "lists": {
+                    "pageInfo": { "hasNextPage": true, "endCursor": "p1" },
+                    "nodes": [list_json("L1", "a/a")]
+                } } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let lists = client(&server).star_lists().await.unwrap();
+        let names = lists.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["L1", "L101"]);
+        assert_eq!(lists[1].repos[0].full_name, "b/b");
+    }
+
+    #[tokio::test]
+    async fn test_graphql_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/graphql"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": null,
+                "errors": [{ "message": "Your token has not been granted the required scopes" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let err = client(&server).star_lists().await.unwrap_err();
+        assert!(err.to_string().contains("required scopes"));
+    }
+
+    #[tokio::test]
+    async fn test_errors_carry_github_message() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "message": "Bad credentials"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = client(&server).user().await.unwrap_err();
+        assert!(err.to_string().contains("401"));
+        assert!(err.to_string().contains("Bad credentials"));
+    }
+
+    #[tokio::test]
+    async fn test_long_rate_limit_fails_fast() {
+        let server = MockServer::start().await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .insert_header("x-ratelimit-remaining", "0")
+                    .insert_header("x-ratelimit-reset", (now + 3600).to_string()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = client(&server).user().await.unwrap_err();
+        assert!(err.to_string().contains("rate limit"));
+    }
+}
@@ -52,24 +52,59 @@ fn get_more_link(document: &scraper::Html) -> Option<String> {
         .and_then(|el| el.value().attr("href").map(|s| s.to_string()))
 }
 
+fn is_logged_in(document: &scraper::Html) -> bool {
+    let logout_selector = scraper::Selector::parse("a#logout").expect("Failed to parse selector");
+    document.select(&logout_selector).next().is_some()
+}
+
 #[derive(Debug, Clone)]
 pub struct HNPost {
     pub title: String,
     pub url: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct HNPage {
+    pub posts: Vec<HNPost>,
+    /// Path of the following page, which `stream_pages` can start from to
+    /// resume after this page.
+    pub next_path: Option<String>,
+}
+
+/// Streams the pages starting at `start_path`. A failed request is yielded as
+/// an error and ends the stream.
 pub fn stream_pages(
     hn_auth: &str,
     start_path: String,
-) -> anyhow::Result<impl futures::Stream<Item = Vec<HNPost>>> {
+) -> anyhow::Result<impl futures::Stream<Item = anyhow::Result<HNPage>>> {
     stream_pages_with_base_url(hn_auth, start_path, HN_BASE_URL)
 }
 
+/// Checks that `hn_auth` is a valid session cookie by loading the front page
+/// and looking for the logout link.
+pub async fn check_auth(hn_auth: &str) -> anyhow::Result<()> {
+    check_auth_with_base_url(hn_auth, HN_BASE_URL).await
+}
+
+async fn check_auth_with_base_url(hn_auth: &str, base_url: &str) -> anyhow::Result<()> {
+    let client = get_hn_client(hn_auth)?;
+    let resp = client.get(format!("{base_url}/")).send().await?;
+    if !resp.status().is_success() {
+        anyhow::bail!("Hacker News returned {}", resp.status());
+    }
+
+    let body = resp.text().await?;
+    if !is_logged_in(&scraper::Html::parse_document(&body)) {
+        anyhow::bail!("Hacker News auth cookie was not accepted");
+    }
+    Ok(())
+}
+
 fn stream_pages_with_base_url(
     hn_auth: &str,
     start_path: String,
     base_url: &str,
-) -> anyhow::Result<impl futures::Stream<Item = Vec<HNPost>>> {
+) -> anyhow::Result<impl futures::Stream<Item = anyhow::Result<HNPage>>> {
     let client = get_hn_client(hn_auth)?;
     let base_url = base_url.to_string();
 
@@ -77,28 +112,13 @@ fn stream_pages_with_base_url(
         let client = Arc::clone(&client);
         let base_url = base_url.clone();
         async move {
-            path.as_ref()?;
-
-            let url = format!("{}/{}", base_url, path.unwrap());
-            let response = client.get(&url).send().await;
-
-            match response {
-                Ok(resp) => {
-                    if !resp.status().is_success() {
-                        return None;
-                    }
-
-                    match resp.text().await {
-                        Ok(body) => {
-                            let document = scraper::Html::parse_document(&body);
-                            let submissions = get_upvoted_submissions_from_document(&document);
-                            let more_link = get_more_link(&document);
-                            Some((submissions, more_link))
-                        }
-                        Err(_) => None,
-                    }
+            let url = format!("{}/{}", base_url, path?);
+            match fetch_page(&client, &url).await {
+                Ok(page) => {
+                    let next_path = page.next_path.clone();
+                    Some((Ok(page), next_path))
                 }
-                Err(_) => None,
+                Err(e) => Some((Err(e), None)),
             }
         }
     });
@@ -106,6 +126,20 @@ fn stream_pages_with_base_url(
     Ok(pages)
 }
 
+async fn fetch_page(client: &Client, url: &str) -> anyhow::Result<HNPage> {
+    let resp = client.get(url).send().await?;
+    if !resp.status().is_success() {
+        anyhow::bail!("Hacker News returned {} for {}", resp.status(), url);
+    }
+
+    let body = resp.text().await?;
+    let document = scraper::Html::parse_document(&body);
+    Ok(HNPage {
+        posts: get_upvoted_submissions_from_document(&document),
+        next_path: get_more_link(&document),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,6 +222,17 @@ mod tests {
         assert!(more_link.is_none());
     }
 
+    #[test]
+    fn test_is_logged_in() {
+        let document = Html::parse_document(
+            r#"<html><body><a id="logout" href="logout">logout</a></body></html>"#,
+        );
+        assert!(is_logged_in(&document));
+
+        let document = Html::parse_document(SAMPLE_HN_HTML);
+        assert!(!is_logged_in(&document));
+    }
+
     #[test]
     fn test_get_hn_client_valid_auth() {
         let result = get_hn_client("test_user_auth_token");
@@ -262,20 +307,51 @@ mod tests {
         let stream =
             stream_pages_with_base_url("test_auth", "upvoted".to_string(), &base_uri).unwrap();
 
-        let pages: Vec<_> = stream.take(2).collect().await;
+        let pages: Vec<_> = stream.take(2).map(Result::unwrap).collect().await;
 
         // Verify we got 2 pages
         assert_eq!(pages.len(), 2);
 
         // Verify first page content
-        assert_eq!(pages[0].len(), 1);
-        assert_eq!(pages[0][0].title, "Test Story 1");
-        assert_eq!(pages[0][0].url, "https://example.com/story1");
+        assert_eq!(pages[0].posts.len(), 1);
+        assert_eq!(pages[0].posts[0].title, "Test Story 1");
+        assert_eq!(pages[0].posts[0].url, "https://example.com/story1");
+        assert_eq!(pages[0].next_path.as_deref(), Some("?p=2"));
 
         // Verify second page content
-        assert_eq!(pages[1].len(), 1);
-        assert_eq!(pages[1][0].title, "Test Story 2");
-        assert_eq!(pages[1][0].url, "https://example.com/story2");
+        assert_eq!(pages[1].posts.len(), 1);
+        assert_eq!(pages[1].posts[0].title, "Test Story 2");
+        assert_eq!(pages[1].posts[0].url, "https://example.com/story2");
+        assert_eq!(pages[1].next_path, None);
+    }
+
+    #[tokio::test]
+    async fn test_check_auth() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let logged_in_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<html><body><a id="logout" href="logout">logout</a></body></html>"#,
+            ))
+            .mount(&logged_in_server)
+            .await;
+
+        // HN serves the front page without a logout link for a rejected cookie
+        let logged_out_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(SAMPLE_HN_HTML))
+            .mount(&logged_out_server)
+            .await;
+
+        let result = check_auth_with_base_url("test_auth", &logged_in_server.uri()).await;
+        assert!(result.is_ok());
+
+        let result = check_auth_with_base_url("test_auth", &logged_out_server.uri()).await;
+        assert!(result.is_err());
     }
 
     #[tokio::test]
@@ -313,13 +389,13 @@ mod tests {
         let stream =
             stream_pages_with_base_url("test_auth", "upvoted".to_string(), &base_uri).unwrap();
 
-        let pages: Vec<_> = stream.collect().await;
+        let pages: Vec<_> = stream.map(Result::unwrap).collect().await;
 
         // Should only get one page since there's no "more" link
         assert_eq!(pages.len(), 1);
-        assert_eq!(pages[0].len(), 1);
-        assert_eq!(pages[0][0].title, "Single Story");
-        assert_eq!(pages[0][0].url, "https://example.com/single-story");
+        assert_eq!(pages[0].posts.len(), 1);
+        assert_eq!(pages[0].posts[0].title, "Single Story");
+        assert_eq!(pages[0].posts[0].url, "https://example.com/single-story");
     }
 
     #[tokio::test]
@@ -343,8 +419,9 @@ mod tests {
 
         let pages: Vec<_> = stream.collect().await;
 
-        // Should get no pages due to HTTP error
-        assert_eq!(pages.len(), 0);
+        // Should get a single error and no pages due to HTTP error
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].is_err());
     }
 
     #[tokio::test]
@@ -378,6 +455,6 @@ mod tests {
 
         // Should get one page but with empty submissions
         assert_eq!(pages.len(), 1);
-        assert_eq!(pages[0].len(), 0);
+        assert_eq!(pages[0].as_ref().unwrap().posts.len(), 0);
     }
 }
@@ -1,14 +1,248 @@
-use reqwest::{Client, Response, Url};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use reqwest::{Client, RequestBuilder, Response, Url};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Receives the timing of every Karakeep API call, e.g. to export metrics.
+pub trait RequestObserver: Send + Sync {
+    fn observe(&self, operation: &'static str, elapsed: Duration, success: bool);
+}
 
 pub struct KarakeepClient {
     url: String,
     client: Client,
+    observer: Option<Arc<dyn RequestObserver>>,
+}
+
+/// Where a bookmark came from: the plugin that synced it and the item's ID in
+/// that plugin's source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookmarkSource {
+    #[serde(default)]
+    pub plugin: String,
+    pub id: String,
+}
+
+impl BookmarkSource {
+    /// A source for the item `id`, the plugin is filled in when the bookmark
+    /// is synced.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            plugin: String::new(),
+            id: id.into(),
+        }
+    }
+}
+
+/// How far in the future a timestamp may be before it is rejected, to allow
+/// for clock skew between the source and us.
+const MAX_FUTURE_SKEW: TimeDelta = TimeDelta::days(1);
+
+/// A validated point in time, such as when a bookmark was created in its source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct Timestamp(DateTime<Utc>);
+
+impl Timestamp {
+    fn new(datetime: DateTime<Utc>) -> anyhow::Result<Self> {
+        if datetime.timestamp() < 0 {
+            anyhow::bail!("timestamp {datetime} is before 1970");
+        }
+        if datetime > Utc::now() + MAX_FUTURE_SKEW {
+            anyhow::bail!("timestamp {datetime} is in the future");
+        }
+        Ok(Self(datetime))
+    }
+
+    /// Parses an RFC 3339 date-time such as Pinboard's `time` or GitHub's
+    /// `starred_at`, e.g. `2024-01-02T03:04:05Z`.
+    pub fn parse_rfc3339(value: &str) -> anyhow::Result<Self> {
+        let datetime = DateTime::parse_from_rfc3339(value.trim())
+            .with_context(|| format!("invalid timestamp '{value}'"))?;
+        Self::new(datetime.with_timezone(&Utc))
+    }
+
+    /// Converts seconds since the Unix epoch, such as Reddit's `created_utc`.
+    pub fn from_unix_secs(secs: f64) -> anyhow::Result<Self> {
+        let datetime = secs
+            .is_finite()
+            .then(|| DateTime::from_timestamp_millis((secs * 1000.0).round() as i64))
+            .flatten()
+            .ok_or_else(|| anyhow::anyhow!("invalid unix timestamp {secs}"))?;
+        Self::new(datetime)
+    }
+
+    pub fn as_datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        Self::parse_rfc3339(value)
+    }
+}
+
+/// Deserializes through the same validation as `parse_rfc3339`, e.g. for
+/// bookmarks read back from the dead-letter queue.
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value
+            .parse()
+            .map_err(|e| serde::de::Error::custom(format!("{e:#}")))
+    }
+}
+
+/// Formats the timestamp the way Karakeep's API returns them, e.g.
+/// `2024-01-02T03:04:05.000Z`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BookmarkCreate {
     pub title: String,
     pub url: String,
-    pub created_at: Option<String>,
+    pub created_at: Option<Timestamp>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub favourited: bool,
+    #[serde(default)]
+    pub source: Option<BookmarkSource>,
+    /// Name of a list to sync the bookmark to instead of the plugin's own.
+    #[serde(default)]
+    pub list: Option<String>,
+}
+
+impl BookmarkCreate {
+    /// Attributes the bookmark to `plugin`, giving it a source identified by
+    /// its URL if the plugin didn't set one.
+    pub fn set_source_plugin(&mut self, plugin: &str) {
+        let source = self
+            .source
+            .get_or_insert_with(|| BookmarkSource::new(self.url.clone()));
+        source.plugin = plugin.to_string();
+    }
+
+    /// Identifies the item in its source, falling back to the URL for sources
+    /// without stable IDs.
+    pub fn source_id(&self) -> &str {
+        self.source
+            .as_ref()
+            .map(|source| source.id.as_str())
+            .unwrap_or(&self.url)
+    }
+}
+
+/// The parts of an existing Karakeep bookmark that syncing may fill in.
+#[derive(Debug, Clone, Default)]
+struct ExistingBookmark {
+    id: String,
+    note: Option<String>,
+    summary: Option<String>,
+    tags: Vec<String>,
+}
+
+impl ExistingBookmark {
+    fn from_json(bookmark: &serde_json::Value) -> Option<Self> {
+        let text = |key: &str| {
+            bookmark
+                .get(key)
+                .and_then(|v| v.as_str())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string())
+        };
+
+        Some(Self {
+            id: text("id")?,
+            note: text("note"),
+            summary: text("summary"),
+            tags: bookmark
+                .get("tags")
+                .and_then(|t| t.as_array())
+                .map(|tags| {
+                    tags.iter()
+                        .filter_map(|tag| tag.get("name").and_then(|n| n.as_str()))
+                        .map(|name| name.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Fields of `bookmark` that are missing on this bookmark. Syncing only
+    /// ever adds to a bookmark, so notes and summaries set in Karakeep are left
+    /// alone. The archived and favourited flags are only set on creation, the
+    /// user or another plugin may have changed them since.
+    fn missing_fields(
+        &self,
+        bookmark: &BookmarkCreate,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut fields = serde_json::Map::new();
+        if let Some(note) = &bookmark.note
+            && self.note.is_none()
+        {
+            fields.insert("note".to_string(), note.clone().into());
+        }
+        if let Some(summary) = &bookmark.summary
+            && self.summary.is_none()
+        {
+            fields.insert("summary".to_string(), summary.clone().into());
+        }
+        fields
+    }
+
+    fn missing_tags<'a>(&self, bookmark: &'a BookmarkCreate) -> Vec<&'a str> {
+        bookmark
+            .tags
+            .iter()
+            .map(|tag| tag.as_str())
+            .filter(|tag| !self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            .collect()
+    }
+}
+
+/// Where in the list hierarchy to look for a list by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListParent<'a> {
+    /// Anywhere, nested or not.
+    Any,
+    /// Only lists without a parent.
+    TopLevel,
+    /// Only children of the list with this ID.
+    List(&'a str),
+}
+
+impl ListParent<'_> {
+    fn matches(&self, list: &serde_json::Value) -> bool {
+        let parent_id = list.get("parentId").and_then(|p| p.as_str());
+        match self {
+            ListParent::Any => true,
+            ListParent::TopLevel => parent_id.is_none(),
+            ListParent::List(id) => parent_id == Some(*id),
+        }
+    }
+}
+
+pub struct UpsertResult {
+    pub bookmark_id: String,
+    pub created: bool,
+    pub added_to_list: bool,
 }
 
 async fn parse_response(resp: Response) -> anyhow::Result<serde_json::Value> {
@@ -37,9 +271,43 @@ impl KarakeepClient {
         Self {
             url: url.into(),
             client,
+            observer: None,
+        }
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn RequestObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    async fn send(&self, operation: &'static str, req: RequestBuilder) -> anyhow::Result<Response> {
+        let started = Instant::now();
+        let resp = req.send().await;
+
+        if let Some(observer) = &self.observer {
+            let success = resp.as_ref().is_ok_and(|r| r.status().is_success());
+            observer.observe(operation, started.elapsed(), success);
         }
+
+        Ok(resp?)
+    }
+
+    /// Returns the name of the user the API token belongs to, failing if
+    /// Karakeep is unreachable or the token is rejected.
+    pub async fn whoami(&self) -> anyhow::Result<String> {
+        let url = format!("{}/api/v1/users/me", self.url);
+
+        let resp = parse_response(self.send("whoami", self.client.get(&url)).await?).await?;
+
+        let name = resp
+            .get("name")
+            .and_then(|n| n.as_str())
+            .or_else(|| resp.get("email").and_then(|e| e.as_str()))
+            .unwrap_or("unknown user");
+        Ok(name.to_string())
     }
 
+    /// Creates `bookmark` with its note, summary and flags, and attaches its tags.
     pub async fn create_bookmark(&self, bookmark: &BookmarkCreate) -> anyhow::Result<String> {
         let api_url = format!("{}/api/v1/bookmarks", self.url);
         let mut params = serde_json::json!({
@@ -48,41 +316,108 @@ impl KarakeepClient {
             "url": bookmark.url,
         });
 
-        if bookmark.created_at.is_some() {
-            params.as_object_mut().unwrap().insert(
-                "createdAt".to_string(),
-                bookmark.created_at.clone().unwrap().into(),
-            );
+        let fields = params.as_object_mut().unwrap();
+        if let Some(created_at) = bookmark.created_at {
+            fields.insert("createdAt".to_string(), created_at.to_string().into());
+        }
+        fields.extend(ExistingBookmark::default().missing_fields(bookmark));
+        if bookmark.archived {
+            fields.insert("archived".to_string(), true.into());
+        }
+        if bookmark.favourited {
+            fields.insert("favourited".to_string(), true.into());
         }
 
-        let resp = parse_response(self.client.post(&api_url).json(&params).send().await?).await?;
+        let resp = parse_response(
+            self.send("create_bookmark", self.client.post(&api_url).json(&params))
+                .await?,
+        )
+        .await?;
 
-        resp.get("id")
+        let id = resp
+            .get("id")
             .and_then(|id| id.as_str())
             .map(|id| id.to_string())
             .ok_or_else(|| {
                 anyhow::anyhow!(
                     "Failed to create bookmark, response did not contain an ID: {resp:?}"
                 )
-            })
+            })?;
+
+        let tags = bookmark.tags.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+        self.attach_tags(&id, &tags).await?;
+
+        Ok(id)
+    }
+
+    /// Fills in the note, summary and tags of `bookmark` that are missing on
+    /// the existing bookmark. Returns whether anything changed.
+    async fn update_bookmark(
+        &self,
+        existing: &ExistingBookmark,
+        bookmark: &BookmarkCreate,
+    ) -> anyhow::Result<bool> {
+        let fields = existing.missing_fields(bookmark);
+        let tags = existing.missing_tags(bookmark);
+
+        if !fields.is_empty() {
+            let url = format!("{}/api/v1/bookmarks/{}", self.url, existing.id);
+            parse_response(
+                self.send("update_bookmark", self.client.patch(&url).json(&fields))
+                    .await?,
+            )
+            .await?;
+        }
+        self.attach_tags(&existing.id, &tags).await?;
+
+        Ok(!fields.is_empty() || !tags.is_empty())
+    }
+
+    /// Attaches tags to a bookmark by name, creating tags that don't exist yet.
+    pub async fn attach_tags(&self, bookmark_id: &str, tags: &[&str]) -> anyhow::Result<()> {
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        let url = format!("{}/api/v1/bookmarks/{}/tags", self.url, bookmark_id);
+        let params = serde_json::json!({
+            "tags": tags
+                .iter()
+                .map(|tag| serde_json::json!({ "tagName": tag }))
+                .collect::<Vec<_>>(),
+        });
+
+        parse_response(
+            self.send("attach_tags", self.client.post(&url).json(&params))
+                .await?,
+        )
+        .await?;
+        Ok(())
     }
 
     pub async fn check_exists_bookmark(
         &self,
         bookmark_url: &str,
     ) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .find_bookmark(bookmark_url)
+            .await?
+            .map(|bookmark| bookmark.id))
+    }
+
+    async fn find_bookmark(&self, bookmark_url: &str) -> anyhow::Result<Option<ExistingBookmark>> {
         let url = format!("{}/api/v1/bookmarks/search", self.url);
 
         let resp = parse_response(
-            self.client
-                .get(&url)
-                .query(&[
+            self.send(
+                "search_bookmarks",
+                self.client.get(&url).query(&[
                     ("q", bookmark_url),
                     ("includeContent", "false"),
                     ("limit", "1"),
-                ])
-                .send()
-                .await?,
+                ]),
+            )
+            .await?,
         )
         .await?;
 
@@ -109,39 +444,75 @@ impl KarakeepClient {
         let bookmark_url: Url = bookmark_url.unwrap();
 
         if bookmark_url == url_to_check {
-            let id = bookmarks[0]
-                .get("id")
-                .and_then(|id| id.as_str())
-                .map(|s| s.to_string());
-            return Ok(id);
+            return Ok(ExistingBookmark::from_json(&bookmarks[0]));
         }
 
         Ok(None)
     }
 
-    pub async fn ensure_list_exists(&self, list_name: &str) -> anyhow::Result<String> {
+    pub async fn find_list(&self, list_name: &str) -> anyhow::Result<Option<String>> {
+        self.find_list_in(list_name, ListParent::Any).await
+    }
+
+    /// Finds a list by name, only among the lists under `parent`.
+    pub async fn find_list_in(
+        &self,
+        list_name: &str,
+        parent: ListParent<'_>,
+    ) -> anyhow::Result<Option<String>> {
         let url = format!("{}/api/v1/lists", self.url);
 
-        let resp = parse_response(self.client.get(&url).send().await?).await?;
+        let resp = parse_response(self.send("get_lists", self.client.get(&url)).await?).await?;
 
-        let lists = resp.get("lists").and_then(|l| l.as_array()).unwrap();
+        let lists = resp
+            .get("lists")
+            .and_then(|l| l.as_array())
+            .ok_or_else(|| anyhow::anyhow!("Karakeep lists response has no lists: {resp:?}"))?;
 
         for list in lists {
             if list.get("name").and_then(|n| n.as_str()) == Some(list_name)
+                && parent.matches(list)
                 && let Some(id) = list.get("id").and_then(|id| id.as_str())
             {
-                return Ok(id.to_string());
+                return Ok(Some(id.to_string()));
             }
         }
 
+        Ok(None)
+    }
+
+    pub async fn ensure_list_exists(&self, list_name: &str) -> anyhow::Result<String> {
+        self.ensure_list_exists_in(list_name, ListParent::Any).await
+    }
+
+    /// Like `ensure_list_exists`, but only looks under `parent` and creates
+    /// the list there.
+    pub async fn ensure_list_exists_in(
+        &self,
+        list_name: &str,
+        parent: ListParent<'_>,
+    ) -> anyhow::Result<String> {
+        if let Some(id) = self.find_list_in(list_name, parent).await? {
+            return Ok(id);
+        }
+
+        let url = format!("{}/api/v1/lists", self.url);
+
         // If not, create it
-        let params = serde_json::json!({
+        let mut params = serde_json::json!({
             "name": list_name,
             "description": "Auto-created list from karakeep-sync",
             "icon": "🚀"
         });
+        if let ListParent::List(parent_id) = parent {
+            params["parentId"] = parent_id.into();
+        }
 
-        let resp = parse_response(self.client.post(&url).json(&params).send().await?).await?;
+        let resp = parse_response(
+            self.send("create_list", self.client.post(&url).json(&params))
+                .await?,
+        )
+        .await?;
 
         resp.get("id")
             .and_then(|id| id.as_str())
@@ -151,6 +522,90 @@ impl KarakeepClient {
             })
     }
 
+    /// IDs of every bookmark in a list.
+    pub async fn list_bookmark_ids(&self, list_id: &str) -> anyhow::Result<Vec<String>> {
+        let url = format!("{}/api/v1/lists/{}/bookmarks", self.url, list_id);
+        let mut ids = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let mut req = self
+                .client
+                .get(&url)
+                .query(&[("limit", "100"), ("includeContent", "false")]);
+            if let Some(cursor) = &cursor {
+                req = req.query(&[("cursor", cursor)]);
+            }
+            let resp = parse_response(self.send("get_list_bookmarks", req).await?).await?;
+
+            let bookmarks = resp
+                .get("bookmarks")
+                .and_then(|b| b.as_array())
+                .ok_or_else(|| anyhow::anyhow!("Unexpected list bookmarks response: {resp:?}"))?;
+            ids.extend(
+                bookmarks
+                    .iter()
+                    .filter_map(|b| b.get("id").and_then(|id| id.as_str()))
+                    .map(str::to_string),
+            );
+
+            cursor = resp
+                .get("nextCursor")
+                .and_then(|c| c.as_str())
+                .map(str::to_string);
+            if cursor.is_none() {
+                return Ok(ids);
+            }
+        }
+    }
+
+    pub async fn remove_bookmark_from_list(
+        &self,
+        bookmark_id: &str,
+        list_id: &str,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/api/v1/lists/{}/bookmarks/{}",
+            self.url, list_id, bookmark_id
+        );
+        let resp = self
+            .send("remove_from_list", self.client.delete(&url))
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await?;
+            return Err(anyhow::anyhow!("Karakeep API returned {status}: {body}"));
+        }
+
+        Ok(())
+    }
+
+    pub async fn is_bookmark_in_list(
+        &self,
+        bookmark_id: &str,
+        list_id: &str,
+    ) -> anyhow::Result<bool> {
+        let url = format!("{}/api/v1/bookmarks/{}/lists", self.url, bookmark_id);
+
+        let resp = parse_response(
+            self.send("get_bookmark_lists", self.client.get(&url))
+                .await?,
+        )
+        .await?;
+
+        let in_list = resp
+            .get("lists")
+            .and_then(|l| l.as_array())
+            .map(|lists| {
+                lists
+                    .iter()
+                    .any(|list| list.get("id").and_then(|id| id.as_str()) == Some(list_id))
+            })
+            .unwrap_or(false);
+
+        Ok(in_list)
+    }
+
     pub async fn ensure_bookmark_in_list(
         &self,
         bookmark_id: &str,
@@ -160,7 +615,12 @@ impl KarakeepClient {
             "{}/api/v1/lists/{}/bookmarks/{}",
             self.url, list_id, bookmark_id
         );
-        let _resp = self.client.put(&url).send().await?;
+        let resp = self.send("add_to_list", self.client.put(&url)).await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await?;
+            return Err(anyhow::anyhow!("Karakeep API returned {status}: {body}"));
+        }
 
         Ok(())
     }
@@ -169,25 +629,354 @@ impl KarakeepClient {
         &self,
         bookmark: &BookmarkCreate,
         list_id: &str,
-    ) -> anyhow::Result<bool> {
+    ) -> anyhow::Result<UpsertResult> {
         // Check if bookmark exists by URL
         tracing::debug!("checking if bookmark exists: {}", &bookmark.url);
-        let exists = self.check_exists_bookmark(&bookmark.url).await?;
+        let exists = self.find_bookmark(&bookmark.url).await?;
         let to_create = exists.is_none();
         tracing::debug!("bookmark exists: {}", !to_create);
 
-        // If it doesn't exist, create it
-        let bookmark_id: String = if to_create {
-            tracing::info!("creating bookmark: {} - {}", &bookmark.title, &bookmark.url);
-            self.create_bookmark(bookmark).await?
-        } else {
-            exists.unwrap()
+        // If it doesn't exist, create it, otherwise fill in what it is missing
+        let bookmark_id: String = match exists {
+            None => {
+                tracing::info!("creating bookmark: {} - {}", &bookmark.title, &bookmark.url);
+                self.create_bookmark(bookmark).await?
+            }
+            Some(existing) => {
+                if self.update_bookmark(&existing, bookmark).await? {
+                    tracing::debug!("updated bookmark: {} - {}", &existing.id, &bookmark.url);
+                }
+                existing.id
+            }
         };
 
-        tracing::debug!("adding bookmark: {} to list: {}", &bookmark_id, list_id);
         // Either way, make sure that the bookmark is in the specified list
-        self.ensure_bookmark_in_list(&bookmark_id, list_id).await?;
-        // Return true if created, false if already existed
-        Ok(to_create)
+        let add_to_list = to_create || !self.is_bookmark_in_list(&bookmark_id, list_id).await?;
+        if add_to_list {
+            tracing::debug!("adding bookmark: {} to list: {}", &bookmark_id, list_id);
+            self.ensure_bookmark_in_list(&bookmark_id, list_id).await?;
+        }
+
+        Ok(UpsertResult {
+            bookmark_id,
+            created: to_create,
+            added_to_list: add_to_list,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BookmarkCreate, BookmarkSource, KarakeepClient, ListParent, Timestamp};
+    use wiremock::matchers::{body_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn bookmark() -> BookmarkCreate {
+        BookmarkCreate {
+            title: "Example".to_string(),
+            url: "https://example.com/".to_string(),
+            tags: vec!["rust".to_string(), "cli".to_string()],
+            note: Some("a note".to_string()),
+            favourited: true,
+            created_at: Some(Timestamp::parse_rfc3339("2024-01-02T03:04:05Z").unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamps() {
+        let pinboard = Timestamp::parse_rfc3339("2024-01-02T03:04:05Z").unwrap();
+        assert_eq!(pinboard.to_string(), "2024-01-02T03:04:05.000Z");
+
+        let github = Timestamp::parse_rfc3339("2024-01-02T04:04:05+01:00").unwrap();
+        assert_eq!(github, pinboard);
+
+        let reddit = Timestamp::from_unix_secs(1704164645.0).unwrap();
+        assert_eq!(reddit, pinboard);
+        assert_eq!(
+            Timestamp::from_unix_secs(1704164645.25)
+                .unwrap()
+                .to_string(),
+            "2024-01-02T03:04:05.250Z"
+        );
+
+        // round trips through the dead-letter queue
+        let json = serde_json::to_string(&pinboard).unwrap();
+        assert_eq!(serde_json::from_str::<Timestamp>(&json).unwrap(), pinboard);
+        assert_eq!(
+            "2024-01-02T03:04:05Z".parse::<Timestamp>().unwrap(),
+            pinboard
+        );
+    }
+
+    #[test]
+    fn test_deserialize_validates_timestamps() {
+        for invalid in [
+            r#""2024-01-02""#,
+            r#""1969-12-31T23:59:59Z""#,
+            r#""2999-01-01T00:00:00Z""#,
+            "0",
+        ] {
+            assert!(
+                serde_json::from_str::<Timestamp>(invalid).is_err(),
+                "{invalid} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn test_reject_invalid_timestamps() {
+        assert!(Timestamp::parse_rfc3339("").is_err());
+        assert!(Timestamp::parse_rfc3339("2024-01-02").is_err());
+        assert!(Timestamp::parse_rfc3339("2024-13-02T03:04:05Z").is_err());
+        assert!(Timestamp::parse_rfc3339("1969-12-31T23:59:59Z").is_err());
+        assert!(Timestamp::parse_rfc3339("2999-01-01T00:00:00Z").is_err());
+        assert!(Timestamp::from_unix_secs(f64::NAN).is_err());
+        assert!(Timestamp::from_unix_secs(-1.0).is_err());
+        assert!(Timestamp::from_unix_secs(1e20).is_err());
+    }
+
+    #[test]
+    fn test_source_id() {
+        let mut bookmark = bookmark();
+        assert_eq!(bookmark.source_id(), "https://example.com/");
+
+        bookmark.set_source_plugin("hn");
+        assert_eq!(
+            bookmark.source,
+            Some(BookmarkSource {
+                plugin: "hn".to_string(),
+                id: "https://example.com/".to_string(),
+            })
+        );
+
+        bookmark.source = Some(BookmarkSource::new("abc123"));
+        bookmark.set_source_plugin("pinboard");
+        assert_eq!(bookmark.source_id(), "abc123");
+        assert_eq!(bookmark.source.as_ref().unwrap().plugin, "pinboard");
+    }
+
+    #[tokio::test]
+    async fn test_create_bookmark_applies_metadata() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/bookmarks"))
+            .and(body_json(serde_json::json!({
+                "type": "link",
+                "title": "Example",
+                "url": "https://example.com/",
+                "createdAt": "2024-01-02T03:04:05.000Z",
+                "note": "a note",
+                "favourited": true,
+            })))
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(serde_json::json!({ "id": "b1" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/bookmarks/b1/tags"))
+            .and(body_json(serde_json::json!({
+                "tags": [{ "tagName": "rust" }, { "tagName": "cli" }],
+            })))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "attached": [] })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = KarakeepClient::new(&server.uri(), "token");
+        assert_eq!(client.create_bookmark(&bookmark()).await.unwrap(), "b1");
+    }
+
+    #[tokio::test]
+    async fn test_upsert_only_fills_in_missing_metadata() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/bookmarks/search"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "bookmarks": [{
+                    "id": "b1",
+                    "note": "written in karakeep",
+                    "archived": false,
+                    "favourited": false,
+                    "tags": [{ "id": "t1", "name": "Rust" }],
+                    "content": { "type": "link", "url": "https://example.com/" },
+                }],
+            })))
+            .mount(&server)
+            .await;
+        // the note set in Karakeep is kept and the user un-archived and
+        // un-favourited it, so there is nothing to patch
+        Mock::given(method("PATCH"))
+            .and(path("/api/v1/bookmarks/b1"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "id": "b1" })),
+            )
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/bookmarks/b1/tags"))
+            .and(body_json(
+                serde_json::json!({ "tags": [{ "tagName": "cli" }] }),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "attached": [] })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/bookmarks/b1/lists"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "lists": [{ "id": "l1" }] })),
+            )
+            .mount(&server)
+            .await;
+
+        let client = KarakeepClient::new(&server.uri(), "token");
+        let bookmark = BookmarkCreate {
+            archived: true,
+            ..bookmark()
+        };
+        let result = client
+            .upsert_bookmark_to_list(&bookmark, "l1")
+            .await
+            .unwrap();
+        assert_eq!(result.bookmark_id, "b1");
+        assert!(!result.created);
+        assert!(!result.added_to_list);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_list_exists_in_parent() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/lists"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "lists": [
+                    { "id": "top", "name": "Tools", "parentId": null },
+                    { "id": "p1", "name": "GitHub Starred", "parentId": null },
+                ],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/lists"))
+            .and(body_json(serde_json::json!({
+                "name": "Tools",
+                "description": "Auto-created list from karakeep-sync",
+                "icon": "🚀",
+                "parentId": "p1",
+            })))
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(serde_json::json!({ "id": "child" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = KarakeepClient::new(&server.uri(), "token");
+        // a top level list of the same name doesn't count as a child
+        assert_eq!(
+            client
+                .ensure_list_exists_in("Tools", ListParent::List("p1"))
+                .await
+                .unwrap(),
+            "child"
+        );
+        assert_eq!(client.ensure_list_exists("Tools").await.unwrap(), "top");
+    }
+
+    #[tokio::test]
+    async fn test_top_level_list_ignores_nested_namesake() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/lists"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "lists": [
+                    { "id": "nested", "name": "Tools", "parentId": "work" },
+                ],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/lists"))
+            .and(body_json(serde_json::json!({
+                "name": "Tools",
+                "description": "Auto-created list from karakeep-sync",
+                "icon": "🚀",
+            })))
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(serde_json::json!({ "id": "top" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = KarakeepClient::new(&server.uri(), "token");
+        assert_eq!(
+            client.find_list_in("Tools", ListParent::Any).await.unwrap(),
+            Some("nested".to_string())
+        );
+        assert_eq!(
+            client
+                .find_list_in("Tools", ListParent::TopLevel)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            client
+                .ensure_list_exists_in("Tools", ListParent::TopLevel)
+                .await
+                .unwrap(),
+            "top"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_list_fails_on_unexpected_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/lists"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        let client = KarakeepClient::new(&server.uri(), "token");
+        assert!(client.find_list("Tools").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_bookmark_ids_follows_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/lists/l1/bookmarks"))
+            .and(query_param("cursor", "next"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "bookmarks": [{ "id": "b2" }],
+                "nextCursor": null,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/lists/l1/bookmarks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "bookmarks": [{ "id": "b1" }],
+                "nextCursor": "next",
+            })))
+            .mount(&server)
+            .await;
+
+        let client = KarakeepClient::new(&server.uri(), "token");
+        assert_eq!(
+            client.list_bookmark_ids("l1").await.unwrap(),
+            vec!["b1", "b2"]
+        );
     }
 }
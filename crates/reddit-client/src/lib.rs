@@ -1,23 +1,98 @@
-use reqwest::header;
+use std::time::{Duration, Instant};
+
+use reqwest::{StatusCode, header};
 use serde::Deserialize;
+use tokio::sync::Mutex;
 
 const TOKEN_URL: &str = "https://www.reddit.com/api/v1/access_token";
 const APP_URL: &str = "https://oauth.reddit.com";
+/// Access tokens are renewed this long before they expire, so a request
+/// doesn't race the expiry.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+/// Lifetime assumed when Reddit doesn't send `expires_in`.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+/// Reddit hands out its request budget per 10 minute window, so a reset is
+/// never further away than this. Any wait within it is waited out, a longer
+/// one means the headers are off and fails the request.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Wait after a 429 without `Retry-After` or rate limit headers. The budget
+/// refills over the window, a minute of it is enough to get going again.
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+/// 429s in a row before giving up. Every wait already covers what Reddit
+/// asked for, so repeated ones mean something else is using up the budget.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
 
+#[derive(Clone)]
 pub struct RedditClientRefresher {
     refresh_token: String,
     client: reqwest::Client,
 
     client_id: String,
     client_secret: String,
+
+    token_url: String,
+    api_url: String,
 }
 
 pub struct RedditClient {
-    access_token: String,
+    refresher: RedditClientRefresher,
+    access_token: Mutex<AccessToken>,
+    rate_limit: std::sync::Mutex<Option<RateLimit>>,
     client: reqwest::Client,
     username: String,
 }
 
+/// The request budget Reddit reported with its last response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests left in the current window.
+    pub remaining: f64,
+    /// Requests made in the current window.
+    pub used: u64,
+    /// When the window ends and the budget is reset.
+    pub reset_at: Instant,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &header::HeaderMap) -> Option<Self> {
+        let get = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+
+        // reddit sends these as e.g. "598.0", "2" and "412"
+        let remaining = get("x-ratelimit-remaining")?;
+        let reset = get("x-ratelimit-reset")?;
+        let used = get("x-ratelimit-used").unwrap_or_default();
+        Some(Self {
+            remaining,
+            used: used.max(0.0) as u64,
+            reset_at: Instant::now() + Duration::from_secs_f64(reset.max(0.0)),
+        })
+    }
+
+    /// How long to pause before the next request, if the budget is used up.
+    fn wait(&self) -> Option<Duration> {
+        (self.remaining < 1.0)
+            .then(|| self.reset_at.saturating_duration_since(Instant::now()))
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+struct AccessToken {
+    value: String,
+    expires_at: Instant,
+}
+
+impl AccessToken {
+    fn is_fresh(&self) -> bool {
+        Instant::now() + TOKEN_EXPIRY_MARGIN < self.expires_at
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
 impl RedditClientRefresher {
     pub fn new(client_id: String, client_secret: String, refresh_token: String) -> Self {
         let mut headers = header::HeaderMap::new();
@@ -36,10 +111,33 @@ impl RedditClientRefresher {
             client,
             client_id,
             client_secret,
+            token_url: TOKEN_URL.to_string(),
+            api_url: APP_URL.to_string(),
         }
     }
 
+    /// Points the client at other hosts than reddit.com, e.g. a mock server.
+    pub fn with_urls(mut self, token_url: &str, api_url: &str) -> Self {
+        self.token_url = token_url.to_string();
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
     pub async fn refresh(&self, username: String) -> anyhow::Result<RedditClient> {
+        let access_token = self.access_token().await?;
+
+        tracing::info!("Reddit authenticated as u/{}", username);
+
+        Ok(RedditClient {
+            refresher: self.clone(),
+            access_token: Mutex::new(access_token),
+            rate_limit: std::sync::Mutex::new(None),
+            client: self.client.clone(),
+            username,
+        })
+    }
+
+    async fn access_token(&self) -> anyhow::Result<AccessToken> {
         let params = [
             ("grant_type", "refresh_token"),
             ("refresh_token", &self.refresh_token),
@@ -47,28 +145,44 @@ impl RedditClientRefresher {
 
         let resp = self
             .client
-            .post(TOKEN_URL)
+            .post(&self.token_url)
             .basic_auth(&self.client_id, Some(&self.client_secret))
             .form(&params)
             .send()
-            .await?
-            .json::<serde_json::Value>()
             .await?;
-        let access_token = resp
-            .get("access_token")
-            .and_then(|t| t.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Failed to get access token from response: {resp:?}"))?;
-
-        tracing::info!("Reddit authenticated as u/{}", username);
+        let status = resp.status();
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "Reddit token endpoint returned {status}: {body}"
+            ));
+        }
+        let resp = serde_json::from_str::<TokenResponse>(&body).map_err(|e| {
+            anyhow::anyhow!("Failed to get access token from response: {e}\nBody: {body}")
+        })?;
 
-        Ok(RedditClient {
-            access_token: access_token.to_string(),
-            client: self.client.clone(),
-            username,
+        let lifetime = resp
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+        Ok(AccessToken {
+            value: resp.access_token,
+            expires_at: Instant::now() + lifetime,
         })
     }
 }
 
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    resp.headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListingResponse {
     pub data: ListingData,
@@ -86,14 +200,94 @@ pub struct ListingChild {
 }
 #[derive(Debug, Deserialize)]
 pub struct ListingChildData {
+    /// Fullname of the item, e.g. `t3_abc` for a post.
+    pub name: Option<String>,
     pub title: Option<String>,
     pub permalink: String,
+    /// Title of the submission a comment belongs to.
+    pub link_title: Option<String>,
+    /// Text of a comment.
+    pub body: Option<String>,
+    pub author: Option<String>,
+    /// What a link post points to, the post itself for text posts.
+    pub url: Option<String>,
+    #[serde(default)]
+    pub is_self: bool,
+    pub domain: Option<String>,
+    pub subreddit: Option<String>,
+    pub created_utc: Option<f64>,
+    #[serde(default)]
+    pub over_18: bool,
+    pub link_flair_text: Option<String>,
+    pub score: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavedKind {
+    Post,
+    Comment,
 }
 
 #[derive(Debug)]
 pub struct SavedPost {
+    pub kind: SavedKind,
+    /// Fullname of the post or comment, e.g. `t3_abc` or `t1_def`.
+    pub fullname: Option<String>,
     pub title: String,
+    /// Permalink of the post or comment.
     pub url: String,
+    pub author: Option<String>,
+    /// Text of a saved comment.
+    pub body: Option<String>,
+    /// External URL a link post points to.
+    pub link: Option<String>,
+    pub domain: Option<String>,
+    /// Without the `r/` prefix.
+    pub subreddit: Option<String>,
+    /// Unix timestamp of when the post or comment was made.
+    pub created_utc: Option<f64>,
+    pub over_18: bool,
+    pub flair: Option<String>,
+    pub score: Option<i64>,
+}
+
+impl SavedPost {
+    fn from_child(child: ListingChild) -> Self {
+        let data = child.data;
+        // comments are "t1", everything else is treated like a post
+        let kind = if child.kind == "t1" {
+            SavedKind::Comment
+        } else {
+            SavedKind::Post
+        };
+        let title = match kind {
+            SavedKind::Post => data.title,
+            SavedKind::Comment => data.link_title,
+        };
+        // crossposts link to the original post by a relative path
+        let link = data
+            .url
+            .filter(|url| kind == SavedKind::Post && !data.is_self && url.starts_with("http"));
+
+        SavedPost {
+            kind,
+            fullname: data.name,
+            title: title.unwrap_or_else(|| "(unknown title reddit post)".to_string()),
+            url: format!("https://reddit.com{}", data.permalink),
+            author: data.author,
+            body: data.body,
+            link,
+            domain: data.domain,
+            subreddit: data.subreddit,
+            created_utc: data.created_utc,
+            over_18: data.over_18,
+            flair: data
+                .link_flair_text
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty()),
+            score: data.score,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -103,17 +297,86 @@ pub struct ListSavedResponse {
 }
 
 impl RedditClient {
-    pub async fn list_saved(&self, after: Option<&str>) -> anyhow::Result<ListSavedResponse> {
-        let mut req = self
-            .client
-            .get(format!("{}/user/{}/saved", APP_URL, self.username))
-            .bearer_auth(&self.access_token);
+    /// A token that is valid for a while yet, renewed if it is about to
+    /// expire or is `stale`, a token the API rejected.
+    async fn token(&self, stale: Option<&str>) -> anyhow::Result<String> {
+        let mut token = self.access_token.lock().await;
+        // a concurrent request may have renewed the stale token already
+        if token.is_fresh() && stale != Some(token.value.as_str()) {
+            return Ok(token.value.clone());
+        }
+
+        tracing::info!("renewing Reddit access token for u/{}", self.username);
+        *token = self.refresher.access_token().await?;
+        Ok(token.value.clone())
+    }
+
+    /// The request budget as of the last response, `None` before the first
+    /// request or if Reddit didn't report it.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        *self.rate_limit.lock().unwrap()
+    }
+
+    /// GETs an API path, renewing the access token and retrying once if it
+    /// is rejected. Pauses when the rate limit budget is used up and backs
+    /// off when Reddit responds with 429.
+    async fn get(&self, path: &str, query: &[(&str, &str)]) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}{}", self.refresher.api_url, path);
+        let mut stale = None;
+        let mut throttled = 0;
+
+        loop {
+            if let Some(wait) = self.rate_limit().and_then(|limit| limit.wait()) {
+                if wait > RATE_LIMIT_WINDOW {
+                    anyhow::bail!("Reddit rate limit exhausted, it resets in {wait:?}");
+                }
+                tracing::info!("Reddit rate limit exhausted, pausing for {:?}", wait);
+                tokio::time::sleep(wait).await;
+            }
+
+            let token = self.token(stale.as_deref()).await?;
+            let resp = self
+                .client
+                .get(&url)
+                .query(query)
+                .bearer_auth(&token)
+                .send()
+                .await?;
 
-        if let Some(after) = after {
-            req = req.query(&[("after", after)]);
+            let rate_limit = RateLimit::from_headers(resp.headers());
+            if rate_limit.is_some() {
+                *self.rate_limit.lock().unwrap() = rate_limit;
+            }
+
+            match resp.status() {
+                StatusCode::UNAUTHORIZED if stale.is_none() => {
+                    tracing::warn!("Reddit rejected the access token, renewing it");
+                    stale = Some(token);
+                }
+                StatusCode::TOO_MANY_REQUESTS if throttled < MAX_RATE_LIMIT_RETRIES => {
+                    throttled += 1;
+                    let wait = retry_after(&resp)
+                        .or_else(|| rate_limit.and_then(|limit| limit.wait()))
+                        .unwrap_or(RATE_LIMIT_BACKOFF);
+                    if wait > RATE_LIMIT_WINDOW {
+                        anyhow::bail!("Reddit rate limit exceeded, retry in {wait:?}");
+                    }
+                    tracing::warn!("Reddit is rate limiting {}, retrying in {:?}", path, wait);
+                    tokio::time::sleep(wait).await;
+                }
+                _ => return Ok(resp),
+            }
         }
+    }
+
+    pub async fn list_saved(&self, after: Option<&str>) -> anyhow::Result<ListSavedResponse> {
+        let path = format!("/user/{}/saved", self.username);
+        let query = match after {
+            Some(after) => vec![("after", after)],
+            None => vec![],
+        };
 
-        let resp = req.send().await?;
+        let resp = self.get(&path, &query).await?;
         let status = resp.status();
         let body = resp.text().await?;
         if !status.is_success() {
@@ -126,15 +389,7 @@ impl RedditClient {
             .data
             .children
             .into_iter()
-            .map(|child| {
-                let data = child.data;
-                SavedPost {
-                    title: data
-                        .title
-                        .unwrap_or_else(|| "(unknown title reddit post)".to_string()),
-                    url: format!("https://reddit.com{}", data.permalink),
-                }
-            })
+            .map(SavedPost::from_child)
             .collect::<Vec<_>>();
 
         Ok({
@@ -145,3 +400,345 @@ impl RedditClient {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ListingChild, RedditClientRefresher, SavedKind, SavedPost};
+    use std::time::{Duration, Instant};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn token_response(token: &str, expires_in: u64) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": token,
+            "token_type": "bearer",
+            "expires_in": expires_in,
+        }))
+    }
+
+    fn empty_listing() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "data": { "after": null, "children": [] }
+        }))
+    }
+
+    async fn mount_token(server: &MockServer) {
+        Mock::given(method("POST"))
+            .and(path("/api/v1/access_token"))
+            .respond_with(token_response("token", 3600))
+            .mount(server)
+            .await;
+    }
+
+    fn refresher(server: &MockServer) -> RedditClientRefresher {
+        RedditClientRefresher::new("id".into(), "secret".into(), "refresh".into()).with_urls(
+            &format!("{}/api/v1/access_token", server.uri()),
+            &server.uri(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_token_is_renewed_when_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/access_token"))
+            .respond_with(token_response("first", 3600))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/access_token"))
+            .respond_with(token_response("second", 3600))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .and(header("authorization", "Bearer first"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .and(header("authorization", "Bearer second"))
+            .respond_with(empty_listing())
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        client.list_saved(None).await.unwrap();
+        // the renewed token is reused
+        client.list_saved(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_token_is_renewed_before_expiry() {
+        let server = MockServer::start().await;
+        // expires within the renewal margin, so it is renewed before use
+        Mock::given(method("POST"))
+            .and(path("/api/v1/access_token"))
+            .respond_with(token_response("short", 30))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/access_token"))
+            .respond_with(token_response("long", 3600))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .and(header("authorization", "Bearer long"))
+            .respond_with(empty_listing())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        client.list_saved(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_persistent_401_is_an_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/access_token"))
+            .respond_with(token_response("token", 3600))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        let err = client.list_saved(None).await.unwrap_err();
+        assert!(err.to_string().contains("401"));
+    }
+
+    #[test]
+    fn test_saved_post() {
+        let child: ListingChild = serde_json::from_str(
+            r#"{
+                "kind": "t3",
+                "data": {
+                    "name": "t3_abc",
+                    "title": "A post",
+                    "permalink": "/r/rust/comments/abc/a_post/",
+                    "author": "alice",
+                    "subreddit": "rust",
+                    "created_utc": 1704164645.0,
+                    "over_18": true,
+                    "link_flair_text": " Discussion ",
+                    "score": 42
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let post = SavedPost::from_child(child);
+        assert_eq!(post.kind, SavedKind::Post);
+        assert_eq!(post.fullname.as_deref(), Some("t3_abc"));
+        assert_eq!(post.title, "A post");
+        assert_eq!(post.url, "https://reddit.com/r/rust/comments/abc/a_post/");
+        assert_eq!(post.body, None);
+        assert_eq!(post.link, None);
+        assert_eq!(post.subreddit.as_deref(), Some("rust"));
+        assert_eq!(post.created_utc, Some(1704164645.0));
+        assert!(post.over_18);
+        assert_eq!(post.flair.as_deref(), Some("Discussion"));
+        assert_eq!(post.score, Some(42));
+    }
+
+    #[test]
+    fn test_saved_link_post() {
+        let child: ListingChild = serde_json::from_str(
+            r#"{
+                "kind": "t3",
+                "data": {
+                    "title": "An article",
+                    "permalink": "/r/rust/comments/abc/an_article/",
+                    "url": "https://example.com/article",
+                    "is_self": false,
+                    "domain": "example.com"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let post = SavedPost::from_child(child);
+        assert_eq!(
+            post.url,
+            "https://reddit.com/r/rust/comments/abc/an_article/"
+        );
+        assert_eq!(post.link.as_deref(), Some("https://example.com/article"));
+        assert_eq!(post.domain.as_deref(), Some("example.com"));
+
+        let child: ListingChild = serde_json::from_str(
+            r#"{
+                "kind": "t3",
+                "data": {
+                    "title": "A text post",
+                    "permalink": "/r/rust/comments/abc/a_text_post/",
+                    "url": "https://www.reddit.com/r/rust/comments/abc/a_text_post/",
+                    "is_self": true,
+                    "domain": "self.rust"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(SavedPost::from_child(child).link, None);
+    }
+
+    #[test]
+    fn test_saved_comment() {
+        let child: ListingChild = serde_json::from_str(
+            r#"{
+                "kind": "t1",
+                "data": {
+                    "link_title": "A post",
+                    "permalink": "/r/rust/comments/abc/a_post/def/",
+                    "body": "Great point",
+                    "author": "bob"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let comment = SavedPost::from_child(child);
+        assert_eq!(comment.kind, SavedKind::Comment);
+        assert_eq!(comment.title, "A post");
+        assert_eq!(
+            comment.url,
+            "https://reddit.com/r/rust/comments/abc/a_post/def/"
+        );
+        assert_eq!(comment.author.as_deref(), Some("bob"));
+        assert_eq!(comment.body.as_deref(), Some("Great point"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_is_exposed() {
+        let server = MockServer::start().await;
+        mount_token(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(
+                empty_listing()
+                    .insert_header("x-ratelimit-remaining", "598.0")
+                    .insert_header("x-ratelimit-used", "2")
+                    .insert_header("x-ratelimit-reset", "412"),
+            )
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        assert_eq!(client.rate_limit(), None);
+        client.list_saved(None).await.unwrap();
+
+        let limit = client.rate_limit().unwrap();
+        assert_eq!(limit.remaining, 598.0);
+        assert_eq!(limit.used, 2);
+        let reset_in = limit.reset_at - Instant::now();
+        assert!(reset_in > Duration::from_secs(400) && reset_in <= Duration::from_secs(412));
+    }
+
+    #[tokio::test]
+    async fn test_pauses_when_budget_is_exhausted() {
+        let server = MockServer::start().await;
+        mount_token(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(
+                empty_listing()
+                    .insert_header("x-ratelimit-remaining", "0.0")
+                    .insert_header("x-ratelimit-used", "600")
+                    .insert_header("x-ratelimit-reset", "1"),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(
+                empty_listing()
+                    .insert_header("x-ratelimit-remaining", "599.0")
+                    .insert_header("x-ratelimit-used", "1")
+                    .insert_header("x-ratelimit-reset", "600"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        client.list_saved(None).await.unwrap();
+        assert_eq!(client.rate_limit().unwrap().remaining, 0.0);
+
+        let started = Instant::now();
+        client.list_saved(None).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert_eq!(client.rate_limit().unwrap().remaining, 599.0);
+    }
+
+    #[tokio::test]
+    async fn test_backs_off_when_throttled() {
+        let server = MockServer::start().await;
+        mount_token(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(empty_listing())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        let started = Instant::now();
+        client.list_saved(None).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_still_throttled() {
+        let server = MockServer::start().await;
+        mount_token(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .expect(4)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        let err = client.list_saved(None).await.unwrap_err();
+        assert!(err.to_string().contains("429"));
+    }
+
+    #[tokio::test]
+    async fn test_long_reset_fails_instead_of_waiting() {
+        let server = MockServer::start().await;
+        mount_token(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/user/me/saved"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "3600"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = refresher(&server).refresh("me".into()).await.unwrap();
+        let err = client.list_saved(None).await.unwrap_err();
+        assert!(err.to_string().contains("rate limit"));
+    }
+}
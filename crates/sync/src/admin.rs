@@ -0,0 +1,401 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::output::PluginInfo;
+use crate::report::SyncReport;
+use crate::runner::{Runner, Trigger};
+use crate::state::StateStore;
+use crate::{karakeep, metrics};
+
+const DEFAULT_RUNS_LIMIT: usize = 10;
+const MAX_RUNS_LIMIT: usize = 100;
+
+#[derive(Clone)]
+pub struct AdminState {
+    runner: Arc<Runner>,
+    store: &'static StateStore,
+    token: Option<Arc<str>>,
+}
+
+impl AdminState {
+    pub fn new(runner: Arc<Runner>, store: &'static StateStore, token: Option<String>) -> Self {
+        Self {
+            runner,
+            store,
+            token: token.filter(|t| !t.is_empty()).map(Arc::from),
+        }
+    }
+}
+
+fn router(state: AdminState) -> Router {
+    let router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics));
+
+    // the control endpoints can start syncs, so they are only served when
+    // they can be protected by a token
+    if state.token.is_none() {
+        return router;
+    }
+
+    let control = Router::new()
+        .route("/plugins", get(list_plugins))
+        .route("/plugins/{name}/sync", post(trigger_sync))
+        .route("/plugins/{name}/runs", get(list_runs))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state);
+
+    router.merge(control)
+}
+
+/// Binds `listen` and serves the admin endpoints in the background.
+pub async fn spawn(listen: &str, state: AdminState) -> anyhow::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(listen).await?;
+    tracing::info!("admin server listening on {}", listener.local_addr()?);
+    if state.token.is_none() {
+        tracing::info!("KS_ADMIN_TOKEN is not set, control endpoints are disabled");
+    }
+
+    Ok(tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router(state)).await {
+            tracing::error!("admin server failed: {:#}", e);
+        }
+    }))
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Ready once Karakeep is reachable and accepts our API token.
+async fn readyz() -> (StatusCode, String) {
+    match karakeep::get_client().whoami().await {
+        Ok(_) => (StatusCode::OK, "ok".to_string()),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("karakeep unavailable: {e:#}"),
+        ),
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    match metrics::get_metrics().render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            body,
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("failed to render metrics: {e:#}"),
+        ),
+    }
+}
+
+/// Compares without short-circuiting so the token can't be guessed by timing.
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+async fn require_token(State(state): State<AdminState>, req: Request, next: Next) -> Response {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+
+    match (state.token.as_deref(), given) {
+        (Some(expected), Some(given)) if token_matches(expected, given) => next.run(req).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            error_body("invalid or missing token"),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn error_body(error: impl Into<String>) -> Json<ErrorBody> {
+    Json(ErrorBody {
+        error: error.into(),
+    })
+}
+
+fn internal_error(e: anyhow::Error) -> (StatusCode, Json<ErrorBody>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        error_body(format!("{e:#}")),
+    )
+}
+
+#[derive(Debug, Serialize)]
+struct PluginStatus {
+    #[serde(flatten)]
+    info: PluginInfo,
+    running: bool,
+}
+
+async fn list_plugins(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<PluginStatus>>, (StatusCode, Json<ErrorBody>)> {
+    state
+        .runner
+        .plugins()
+        .iter()
+        .map(|plugin| {
+            Ok(PluginStatus {
+                info: PluginInfo::new(plugin.as_ref(), state.store)?,
+                running: state.runner.is_running(plugin.name()),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(Json)
+        .map_err(internal_error)
+}
+
+async fn trigger_sync(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    let Some(plugin) = state.runner.find(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            error_body(format!("unknown plugin '{name}'")),
+        )
+            .into_response();
+    };
+    if !plugin.is_activated() {
+        return (
+            StatusCode::CONFLICT,
+            error_body(format!("plugin '{name}' is not activated")),
+        )
+            .into_response();
+    }
+
+    tracing::info!(
+        "starting on-demand sync job for list: {}",
+        plugin.list_name()
+    );
+    let status = match state.runner.spawn(plugin) {
+        Trigger::Started => "started",
+        Trigger::Queued => "queued",
+        Trigger::Skipped => {
+            return (
+                StatusCode::CONFLICT,
+                error_body(format!("a sync of plugin '{name}' is already running")),
+            )
+                .into_response();
+        }
+    };
+
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "plugin": name, "status": status })),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct RunsQuery {
+    limit: Option<usize>,
+}
+
+async fn list_runs(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Query(query): Query<RunsQuery>,
+) -> Result<Json<Vec<SyncReport>>, (StatusCode, Json<ErrorBody>)> {
+    if state.runner.find(&name).is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            error_body(format!("unknown plugin '{name}'")),
+        ));
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_RUNS_LIMIT)
+        .min(MAX_RUNS_LIMIT);
+    state
+        .store
+        .recent_runs(&name, limit)
+        .map(Json)
+        .map_err(internal_error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AdminState, router, token_matches};
+    use crate::report::SyncReport;
+    use crate::runner::Runner;
+    use crate::runner::test::blocking_plugin;
+    use crate::state::StateStore;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    async fn spawn_server(state: AdminState) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(state)).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    fn admin_state(runner: Arc<Runner>, token: Option<&str>) -> AdminState {
+        let store = Box::leak(Box::new(StateStore::open_in_memory().unwrap()));
+        AdminState::new(runner, store, token.map(str::to_string))
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secret", "secreT"));
+        assert!(!token_matches("secret", "secret2"));
+        assert!(!token_matches("secret", ""));
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let base = spawn_server(admin_state(Arc::new(Runner::new(vec![])), None)).await;
+        let resp = reqwest::get(format!("{base}/healthz")).await.unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(resp.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        crate::metrics::get_metrics().observe_run_error("hn");
+
+        let base = spawn_server(admin_state(Arc::new(Runner::new(vec![])), None)).await;
+        let resp = reqwest::get(format!("{base}/metrics")).await.unwrap();
+        assert!(resp.status().is_success());
+        assert!(
+            resp.headers()["content-type"]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let body = resp.text().await.unwrap();
+        assert!(body.contains(r#"karakeep_sync_runs_total{plugin="hn",result="error"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_control_endpoints_disabled_without_token() {
+        let base = spawn_server(admin_state(Arc::new(Runner::new(vec![])), None)).await;
+        let resp = reqwest::get(format!("{base}/plugins")).await.unwrap();
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_control_endpoints_require_token() {
+        let base = spawn_server(admin_state(Arc::new(Runner::new(vec![])), Some("secret"))).await;
+        let client = reqwest::Client::new();
+
+        let resp = client.get(format!("{base}/plugins")).send().await.unwrap();
+        assert_eq!(resp.status(), 401);
+
+        let resp = client
+            .get(format!("{base}/plugins"))
+            .bearer_auth("wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 401);
+
+        let resp = client
+            .get(format!("{base}/plugins"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_trigger_sync() {
+        let plugin = blocking_plugin();
+        let runner = Arc::new(Runner::new(vec![plugin.clone()]));
+        let base = spawn_server(admin_state(runner.clone(), Some("secret"))).await;
+        let client = reqwest::Client::new();
+
+        let resp = client
+            .post(format!("{base}/plugins/blocking/sync"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 202);
+        assert!(runner.is_running("blocking"));
+
+        let plugins = client
+            .get(format!("{base}/plugins"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(plugins[0]["name"], "blocking");
+        assert_eq!(plugins[0]["running"], true);
+
+        // a second run of the same plugin is refused while the first is in flight
+        let resp = client
+            .post(format!("{base}/plugins/blocking/sync"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 409);
+
+        let resp = client
+            .post(format!("{base}/plugins/unknown/sync"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 404);
+
+        plugin.release.notify_one();
+    }
+
+    #[tokio::test]
+    async fn test_list_runs() {
+        let runner = Arc::new(Runner::new(vec![blocking_plugin()]));
+        let state = admin_state(runner, Some("secret"));
+        for created in 1..=3 {
+            let mut report = SyncReport::new("blocking");
+            report.created = created;
+            state.store.record_run(&report).unwrap();
+        }
+        let base = spawn_server(state).await;
+
+        let runs = reqwest::Client::new()
+            .get(format!("{base}/plugins/blocking/runs?limit=2"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json::<Vec<SyncReport>>()
+            .await
+            .unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].created, 3);
+    }
+}
@@ -0,0 +1,301 @@
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use tokio_cron_scheduler::Job;
+
+use crate::output::{self, OutputFormat, PluginInfo};
+use crate::plugin::{self, Plugin, SyncOptions};
+use crate::report::SyncReport;
+use crate::{karakeep, settings, shutdown, state};
+
+/// A plugin run failed outright, e.g. its source or Karakeep was unreachable.
+const EXIT_SYNC_FAILED: u8 = 1;
+/// Unknown or deactivated plugin requested. Matches clap's own usage errors.
+const EXIT_USAGE: u8 = 2;
+/// Every plugin ran but some items failed and were queued for a retry.
+const EXIT_ITEMS_FAILED: u8 = 3;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Sync links from various services to Karakeep")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run as a daemon, syncing every activated plugin on its schedule (default)
+    Run,
+
+    /// Sync plugins once and exit
+    Sync {
+        /// Plugins to sync, by name (default: every activated plugin)
+        plugins: Vec<String>,
+
+        /// Only read from Karakeep and print the bookmarks, lists and list
+        /// memberships a sync would create
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Retry every item in the dead-letter queue, including those that
+        /// used up their attempts, with a fresh set of attempts
+        #[arg(long, conflicts_with = "dry_run")]
+        retry_failed: bool,
+
+        /// Output format for the sync reports
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+
+    /// List registered plugins with their activation status and schedule
+    Plugins {
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+
+    /// Validate settings, schedules and credentials of every activated plugin
+    Check,
+}
+
+fn plugin_names(plugins: &[Box<dyn Plugin>]) -> String {
+    plugins
+        .iter()
+        .map(|p| p.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub async fn sync(
+    names: &[String],
+    dry_run: bool,
+    retry_failed: bool,
+    format: OutputFormat,
+) -> ExitCode {
+    let mut plugins = plugin::get_plugins();
+
+    if names.is_empty() {
+        plugins.retain(|p| p.is_activated());
+    } else {
+        for name in names {
+            let Some(plugin) = plugins.iter().find(|p| p.name() == name) else {
+                eprintln!(
+                    "unknown plugin '{name}', expected one of: {}",
+                    plugin_names(&plugins)
+                );
+                return ExitCode::from(EXIT_USAGE);
+            };
+            if !plugin.is_activated() {
+                eprintln!("plugin '{name}' is not activated, check its settings");
+                return ExitCode::from(EXIT_USAGE);
+            }
+        }
+        plugins.retain(|p| names.iter().any(|name| name == p.name()));
+    }
+
+    if retry_failed {
+        let store = state::get_store();
+        for plugin in &plugins {
+            match store.requeue_failures(plugin.name()) {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(
+                    "requeued failed items for plugin: {} (count={})",
+                    plugin.name(),
+                    count
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "failed to requeue failed items of '{}': {e:#}",
+                        plugin.name()
+                    );
+                    return ExitCode::from(EXIT_SYNC_FAILED);
+                }
+            }
+        }
+    }
+
+    let options = SyncOptions {
+        dry_run,
+        ..Default::default()
+    };
+    let cancel = options.cancel.clone();
+    tokio::spawn(async move {
+        if shutdown::wait_for_signal().await.is_ok() {
+            tracing::info!("shutting down, stopping after the current item");
+            cancel.cancel();
+        }
+    });
+    let mut reports = Vec::new();
+    let mut run_failed = false;
+
+    for plugin in plugins {
+        // a shutdown stops the current plugin, the rest don't start
+        if options.cancel.is_cancelled() {
+            tracing::info!("shutting down, skipping sync of plugin: {}", plugin.name());
+            break;
+        }
+        match plugin.sync(&options).await {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                tracing::error!("sync failed for list '{}': {:#}", plugin.list_name(), e);
+                run_failed = true;
+            }
+        }
+    }
+
+    if let Err(e) = output::print_reports(&reports, format) {
+        eprintln!("failed to print sync reports: {e:#}");
+        return ExitCode::from(EXIT_SYNC_FAILED);
+    }
+
+    exit_code(run_failed, &reports)
+}
+
+fn exit_code(run_failed: bool, reports: &[SyncReport]) -> ExitCode {
+    // a source that failed mid-run is as much a failed run as one that
+    // couldn't start
+    if run_failed || reports.iter().any(|r| r.source_error.is_some()) {
+        ExitCode::from(EXIT_SYNC_FAILED)
+    } else if reports.iter().any(|r| r.failed > 0) {
+        ExitCode::from(EXIT_ITEMS_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+pub fn plugins(format: OutputFormat) -> anyhow::Result<()> {
+    let store = state::try_get_store()?;
+
+    let plugins = plugin::get_plugins()
+        .iter()
+        .map(|plugin| PluginInfo::new(plugin.as_ref(), store))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    output::print_plugins(&plugins, format)
+}
+
+/// Prints one line per check and remembers whether any of them failed.
+#[derive(Default)]
+struct Checks {
+    failed: bool,
+}
+
+impl Checks {
+    fn record(&mut self, what: &str, result: anyhow::Result<String>) {
+        match result {
+            Ok(detail) if detail.is_empty() => println!("ok    {what}"),
+            Ok(detail) => println!("ok    {what}: {detail}"),
+            Err(e) => {
+                self.failed = true;
+                println!("FAIL  {what}: {e:#}");
+            }
+        }
+    }
+}
+
+fn validate_schedule(schedule: &str) -> anyhow::Result<()> {
+    Job::new(schedule, |_uuid, _l| {})
+        .map(|_| ())
+        .map_err(|e| anyhow::anyhow!("invalid schedule '{schedule}': {e:?}"))
+}
+
+pub async fn check() -> ExitCode {
+    let mut checks = Checks::default();
+
+    let settings = settings::try_get_settings();
+    let settings_ok = settings.is_ok();
+    checks.record("settings", settings.map(|_| String::new()));
+    if !settings_ok {
+        return ExitCode::FAILURE;
+    }
+
+    checks.record(
+        "state store",
+        state::try_get_store().map(|_| settings::get_settings().data.dir.clone()),
+    );
+
+    let karakeep = karakeep::get_client()
+        .whoami()
+        .await
+        .map(|user| format!("authenticated as {user}"));
+    checks.record("karakeep", karakeep);
+
+    for plugin in plugin::get_plugins() {
+        let name = plugin.name();
+        if !plugin.is_activated() {
+            println!("skip  {name}: not activated");
+            continue;
+        }
+
+        let schedule = plugin.recurring_schedule();
+        checks.record(
+            &format!("{name} schedule"),
+            validate_schedule(&schedule).map(|_| schedule),
+        );
+        checks.record(
+            &format!("{name} credentials"),
+            plugin.check().await.map(|_| String::new()),
+        );
+    }
+
+    if checks.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cli, Command, EXIT_ITEMS_FAILED, EXIT_SYNC_FAILED, exit_code, validate_schedule};
+    use crate::report::SyncReport;
+    use clap::Parser;
+    use std::process::ExitCode;
+
+    #[test]
+    fn test_exit_code() {
+        let ok = SyncReport::new("hn");
+        let mut items_failed = SyncReport::new("reddit");
+        items_failed.record_failure("https://example.com", &anyhow::anyhow!("boom"));
+        let mut source_failed = SyncReport::new("github");
+        source_failed.record_source_error(&anyhow::anyhow!("GitHub returned 502"));
+
+        assert_eq!(
+            exit_code(false, std::slice::from_ref(&ok)),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(
+            exit_code(false, &[ok.clone(), items_failed.clone()]),
+            ExitCode::from(EXIT_ITEMS_FAILED)
+        );
+        assert_eq!(
+            exit_code(false, &[items_failed, source_failed]),
+            ExitCode::from(EXIT_SYNC_FAILED)
+        );
+        assert_eq!(exit_code(true, &[ok]), ExitCode::from(EXIT_SYNC_FAILED));
+    }
+
+    #[test]
+    fn test_retry_failed_flag() {
+        let cli = Cli::try_parse_from(["karakeep-sync", "sync", "--retry-failed", "hn"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Sync {
+                retry_failed: true,
+                ..
+            })
+        ));
+        // a dry run doesn't touch the sync state
+        assert!(
+            Cli::try_parse_from(["karakeep-sync", "sync", "--retry-failed", "--dry-run"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_schedule() {
+        assert!(validate_schedule("@daily").is_ok());
+        assert!(validate_schedule("@hourly").is_ok());
+        assert!(validate_schedule("0 0 * * * *").is_ok());
+        assert!(validate_schedule("0 61 * * * *").is_err());
+        assert!(validate_schedule("@fortnightly").is_err());
+    }
+}
@@ -1,11 +1,13 @@
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 
 use karakeep_client::KarakeepClient;
 
+use crate::metrics::KarakeepObserver;
+
 static CLIENT: OnceLock<KarakeepClient> = OnceLock::new();
 pub fn get_client() -> &'static KarakeepClient {
     CLIENT.get_or_init(|| {
         let settings = &crate::settings::get_settings().karakeep;
-        KarakeepClient::new(&settings.url, &settings.auth)
+        KarakeepClient::new(&settings.url, &settings.auth).with_observer(Arc::new(KarakeepObserver))
     })
 }
@@ -1,17 +1,35 @@
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::signal;
+use clap::Parser;
 use tokio_cron_scheduler::{Job, JobScheduler};
 
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
+use crate::admin::AdminState;
+use crate::cli::{Cli, Command};
+use crate::plugin::Plugin;
+use crate::runner::Runner;
+
+mod admin;
+mod cli;
 mod karakeep;
+mod metrics;
+mod output;
 mod plugin;
+mod report;
+mod runner;
 mod settings;
+mod shutdown;
+mod state;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+
     let subscriber = FmtSubscriber::builder()
+        .with_writer(std::io::stderr)
         .with_env_filter(
             EnvFilter::from_default_env()
                 .add_directive("tower=off".parse().unwrap())
@@ -24,14 +42,51 @@ async fn main() -> anyhow::Result<()> {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let mut scheduler = JobScheduler::new().await?;
+    let command = cli.command.unwrap_or(Command::Run);
+
+    // `check` reports configuration problems itself, everything else needs
+    // valid settings and a usable data dir to do anything
+    if !matches!(command, Command::Check) {
+        settings::try_get_settings()?;
+        state::try_get_store()?;
+    }
+
+    match command {
+        Command::Run => run_scheduler().await.map(|_| ExitCode::SUCCESS),
+        Command::Sync {
+            plugins,
+            dry_run,
+            retry_failed,
+            format,
+        } => Ok(cli::sync(&plugins, dry_run, retry_failed, format).await),
+        Command::Plugins { format } => cli::plugins(format).map(|_| ExitCode::SUCCESS),
+        Command::Check => Ok(cli::check().await),
+    }
+}
+
+async fn run_scheduler() -> anyhow::Result<()> {
+    let settings = settings::get_settings();
+    let store = state::get_store();
 
     let plugins = plugin::get_plugins()
         .into_iter()
-        .map(Arc::new)
-        .collect::<Vec<_>>();
+        .map(Arc::from)
+        .collect::<Vec<Arc<dyn Plugin>>>();
+    let runner = Arc::new(
+        Runner::new(plugins)
+            .with_overlap(settings.sync.overlap)
+            .with_concurrency(settings.sync.concurrency),
+    );
 
-    for plugin in plugins {
+    if let Some(listen) = &settings.admin.listen {
+        let state = AdminState::new(runner.clone(), store, settings.admin.token.clone());
+        admin::spawn(listen, state).await?;
+    }
+
+    let mut scheduler = JobScheduler::new().await?;
+
+    for plugin in runner.plugins() {
+        let plugin = plugin.clone();
         let list_name = plugin.list_name();
 
         if !plugin.is_activated() {
@@ -39,18 +94,24 @@ async fn main() -> anyhow::Result<()> {
             continue;
         }
 
+        if let Some(last_run) = store.recent_runs(plugin.name(), 1)?.first() {
+            tracing::info!("previous run for list: {} ({})", list_name, last_run);
+        }
+        if let Some(ts) = store.last_success(plugin.name())? {
+            metrics::get_metrics().set_last_success(plugin.name(), ts as f64);
+        }
+
         if plugin.run_immediate() {
             let plugin = plugin.clone();
-            let job =
-                Job::new_one_shot_async(std::time::Duration::from_millis(10), move |_uuid, _l| {
-                    tracing::info!("starting immediate sync job for list: {}", list_name);
-                    let p = plugin.clone();
-                    Box::pin(async move {
-                        if let Err(e) = p.sync().await {
-                            tracing::error!("sync failed for list '{}': {:#}", list_name, e);
-                        }
-                    })
-                })?;
+            let runner = runner.clone();
+            let job = Job::new_one_shot_async(Duration::from_millis(10), move |_uuid, _l| {
+                tracing::info!("starting immediate sync job for list: {}", list_name);
+                let p = plugin.clone();
+                let runner = runner.clone();
+                Box::pin(async move {
+                    runner.run(&p).await;
+                })
+            })?;
             scheduler.add(job).await?;
         }
 
@@ -61,20 +122,24 @@ async fn main() -> anyhow::Result<()> {
         );
 
         let schedule = plugin.recurring_schedule().to_string();
+        let runner = runner.clone();
         let job = Job::new_async(&schedule, move |_uuid, _l| {
             tracing::info!("starting recurring sync job for list: {}", list_name);
             let p = plugin.clone();
+            let runner = runner.clone();
             Box::pin(async move {
-                if let Err(e) = p.sync().await {
-                    tracing::error!("sync failed for list '{}': {:#}", list_name, e);
-                }
+                runner.run(&p).await;
             })
         })?;
         scheduler.add(job).await?;
     }
 
     scheduler.start().await?;
-    signal::ctrl_c().await?;
+    shutdown::wait_for_signal().await?;
+
+    let grace = Duration::from_secs(settings.shutdown.grace);
+    tracing::info!("shutting down, waiting up to {:?} for running syncs", grace);
+    runner.shutdown(grace).await;
     scheduler.shutdown().await?;
 
     Ok(())
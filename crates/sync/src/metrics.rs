@@ -0,0 +1,245 @@
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use karakeep_client::RequestObserver;
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::report::SyncReport;
+
+/// Prometheus metrics for the daemon, exposed by the admin server on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    bookmarks: IntCounterVec,
+    runs: IntCounterVec,
+    last_success: GaugeVec,
+    sync_duration: HistogramVec,
+    karakeep_requests: HistogramVec,
+    rate_limit_remaining: GaugeVec,
+    rate_limit_used: GaugeVec,
+    rate_limit_reset: GaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let bookmarks = IntCounterVec::new(
+            Opts::new(
+                "karakeep_sync_bookmarks_total",
+                "Bookmarks processed by plugin syncs, by outcome",
+            ),
+            &["plugin", "outcome"],
+        )
+        .unwrap();
+        let runs = IntCounterVec::new(
+            Opts::new("karakeep_sync_runs_total", "Plugin sync runs, by result"),
+            &["plugin", "result"],
+        )
+        .unwrap();
+        let last_success = GaugeVec::new(
+            Opts::new(
+                "karakeep_sync_last_success_timestamp_seconds",
+                "Unix timestamp of the last successful sync of a plugin",
+            ),
+            &["plugin"],
+        )
+        .unwrap();
+        let sync_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "karakeep_sync_duration_seconds",
+                "Duration of plugin sync runs",
+            )
+            .buckets(vec![1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0]),
+            &["plugin"],
+        )
+        .unwrap();
+        let karakeep_requests = HistogramVec::new(
+            HistogramOpts::new(
+                "karakeep_api_request_duration_seconds",
+                "Latency of Karakeep API requests",
+            ),
+            &["operation", "result"],
+        )
+        .unwrap();
+
+        let rate_limit_remaining = GaugeVec::new(
+            Opts::new(
+                "karakeep_sync_source_rate_limit_remaining",
+                "Requests a source API allows until its rate limit resets",
+            ),
+            &["source"],
+        )
+        .unwrap();
+        let rate_limit_used = GaugeVec::new(
+            Opts::new(
+                "karakeep_sync_source_rate_limit_used",
+                "Requests made to a source API in its current rate limit window",
+            ),
+            &["source"],
+        )
+        .unwrap();
+        let rate_limit_reset = GaugeVec::new(
+            Opts::new(
+                "karakeep_sync_source_rate_limit_reset_timestamp_seconds",
+                "Unix timestamp of when the rate limit of a source API resets",
+            ),
+            &["source"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(bookmarks.clone())).unwrap();
+        registry.register(Box::new(runs.clone())).unwrap();
+        registry.register(Box::new(last_success.clone())).unwrap();
+        registry.register(Box::new(sync_duration.clone())).unwrap();
+        registry
+            .register(Box::new(karakeep_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(rate_limit_remaining.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(rate_limit_used.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(rate_limit_reset.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            bookmarks,
+            runs,
+            last_success,
+            sync_duration,
+            karakeep_requests,
+            rate_limit_remaining,
+            rate_limit_used,
+            rate_limit_reset,
+        }
+    }
+
+    pub fn observe_report(&self, report: &SyncReport) {
+        let plugin = report.plugin.as_str();
+        for (outcome, count) in [
+            ("created", report.created),
+            ("existing", report.existing),
+            ("skipped", report.skipped),
+            ("failed", report.failed),
+        ] {
+            self.bookmarks
+                .with_label_values(&[plugin, outcome])
+                .inc_by(count as u64);
+        }
+
+        let result = if report.source_error.is_some() {
+            "error"
+        } else if report.is_success() {
+            "success"
+        } else {
+            "partial"
+        };
+        self.runs.with_label_values(&[plugin, result]).inc();
+        self.sync_duration
+            .with_label_values(&[plugin])
+            .observe(report.duration().as_secs_f64());
+
+        if !report.stopped_early {
+            let finished_at = report.started_at as f64 + report.duration().as_secs_f64();
+            self.set_last_success(plugin, finished_at);
+        }
+    }
+
+    pub fn set_last_success(&self, plugin: &str, timestamp: f64) {
+        self.last_success
+            .with_label_values(&[plugin])
+            .set(timestamp);
+    }
+
+    pub fn observe_run_error(&self, plugin: &str) {
+        self.runs.with_label_values(&[plugin, "error"]).inc();
+    }
+
+    /// Records the rate limit budget a source API last reported.
+    pub fn set_rate_limit(&self, source: &str, remaining: f64, used: u64, resets_in: Duration) {
+        let reset_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            + resets_in;
+        self.rate_limit_remaining
+            .with_label_values(&[source])
+            .set(remaining);
+        self.rate_limit_used
+            .with_label_values(&[source])
+            .set(used as f64);
+        self.rate_limit_reset
+            .with_label_values(&[source])
+            .set(reset_at.as_secs() as f64);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> anyhow::Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// Feeds Karakeep API latencies from `KarakeepClient` into the global metrics.
+pub struct KarakeepObserver;
+
+impl RequestObserver for KarakeepObserver {
+    fn observe(&self, operation: &'static str, elapsed: Duration, success: bool) {
+        let result = if success { "success" } else { "error" };
+        get_metrics()
+            .karakeep_requests
+            .with_label_values(&[operation, result])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+pub fn get_metrics() -> &'static Metrics {
+    &METRICS
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+    use crate::report::SyncReport;
+    use std::time::Duration;
+
+    #[test]
+    fn test_observe_report() {
+        let metrics = Metrics::new();
+
+        let mut report = SyncReport::new("hn");
+        report.started_at = 1000;
+        report.created = 3;
+        report.failed = 1;
+        report.finish(Duration::from_secs(2));
+        metrics.observe_report(&report);
+        metrics.observe_run_error("github");
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(r#"karakeep_sync_bookmarks_total{outcome="created",plugin="hn"} 3"#));
+        assert!(text.contains(r#"karakeep_sync_bookmarks_total{outcome="failed",plugin="hn"} 1"#));
+        assert!(text.contains(r#"karakeep_sync_runs_total{plugin="hn",result="partial"} 1"#));
+        assert!(text.contains(r#"karakeep_sync_runs_total{plugin="github",result="error"} 1"#));
+        assert!(text.contains(r#"karakeep_sync_last_success_timestamp_seconds{plugin="hn"} 1002"#));
+        assert!(text.contains(r#"karakeep_sync_duration_seconds_count{plugin="hn"} 1"#));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let metrics = Metrics::new();
+        metrics.set_rate_limit("reddit", 598.0, 2, Duration::from_secs(412));
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(r#"karakeep_sync_source_rate_limit_remaining{source="reddit"} 598"#));
+        assert!(text.contains(r#"karakeep_sync_source_rate_limit_used{source="reddit"} 2"#));
+        assert!(text.contains(
+            r#"karakeep_sync_source_rate_limit_reset_timestamp_seconds{source="reddit"}"#
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::plugin::Plugin;
+use crate::report::{PlannedAction, SyncReport};
+use crate::state::StateStore;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+pub fn print_reports(reports: &[SyncReport], format: OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(reports)?),
+        OutputFormat::Table => {
+            for report in reports {
+                print!("{}", report_table(report));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub list: String,
+    pub activated: bool,
+    pub schedule: String,
+    pub last_success_at: Option<i64>,
+    /// Items waiting in the dead-letter queue.
+    pub failed_items: u32,
+}
+
+impl PluginInfo {
+    pub fn new(plugin: &dyn Plugin, store: &StateStore) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: plugin.name().to_string(),
+            list: plugin.list_name().to_string(),
+            activated: plugin.is_activated(),
+            schedule: plugin.recurring_schedule(),
+            last_success_at: store.last_success(plugin.name())?,
+            failed_items: store.failed_count(plugin.name())?,
+        })
+    }
+}
+
+pub fn print_plugins(plugins: &[PluginInfo], format: OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(plugins)?),
+        OutputFormat::Table => print!("{}", plugins_table(plugins)),
+    }
+    Ok(())
+}
+
+fn format_timestamp(ts: i64) -> String {
+    DateTime::from_timestamp(ts, 0)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+fn plugins_table(plugins: &[PluginInfo]) -> String {
+    let mut out = format!(
+        "{:<16} {:<16} {:<10} {:<12} {:<24} FAILED ITEMS\n",
+        "NAME", "LIST", "ACTIVATED", "SCHEDULE", "LAST SUCCESS"
+    );
+    for plugin in plugins {
+        let last_success = plugin
+            .last_success_at
+            .map(format_timestamp)
+            .unwrap_or_else(|| "never".to_string());
+        out.push_str(&format!(
+            "{:<16} {:<16} {:<10} {:<12} {:<24} {}\n",
+            plugin.name,
+            plugin.list,
+            if plugin.activated { "yes" } else { "no" },
+            plugin.schedule,
+            last_success,
+            plugin.failed_items
+        ));
+    }
+    out
+}
+
+fn report_table(report: &SyncReport) -> String {
+    let mut out = format!("== {} ==\n", report.plugin);
+
+    // only dry runs plan actions, real runs just get the summary below
+    if report.dry_run && report.actions.is_empty() {
+        out.push_str("no changes\n");
+    } else if report.dry_run {
+        out.push_str(&format!("{:<16} {:<20} TARGET\n", "ACTION", "LIST"));
+        for action in &report.actions {
+            let (kind, list, target) = match action {
+                PlannedAction::CreateList { list } => ("create list", list, String::new()),
+                PlannedAction::CreateBookmark { list, title, url } => {
+                    ("create bookmark", list, format!("{title} <{url}>"))
+                }
+                PlannedAction::AddToList {
+                    list,
+                    bookmark_id,
+                    url,
+                } => (
+                    "add to list",
+                    list,
+                    format!("{url} (bookmark {bookmark_id})"),
+                ),
+                PlannedAction::RemoveFromList { list, bookmark_id } => {
+                    ("remove from list", list, format!("bookmark {bookmark_id}"))
+                }
+            };
+            let line = format!("{kind:<16} {list:<20} {target}");
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+    }
+
+    for failure in &report.failures {
+        out.push_str(&format!("failed: {} ({})\n", failure.url, failure.reason));
+    }
+    out.push_str(&format!("{report}\n\n"));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PluginInfo, plugins_table, report_table};
+    use crate::report::{PlannedAction, SyncReport};
+
+    #[test]
+    fn test_report_table() {
+        let mut report = SyncReport::new("hn");
+        report.dry_run = true;
+        report.created = 1;
+        report.actions = vec![
+            PlannedAction::CreateList {
+                list: "HN Upvoted".to_string(),
+            },
+            PlannedAction::CreateBookmark {
+                list: "HN Upvoted".to_string(),
+                title: "Story".to_string(),
+                url: "https://example.com".to_string(),
+            },
+            PlannedAction::RemoveFromList {
+                list: "Tools".to_string(),
+                bookmark_id: "b1".to_string(),
+            },
+        ];
+
+        let table = report_table(&report);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "== hn ==");
+        assert_eq!(lines[2], "create list      HN Upvoted");
+        assert_eq!(
+            lines[3],
+            "create bookmark  HN Upvoted           Story <https://example.com>"
+        );
+        assert_eq!(
+            lines[4],
+            "remove from list Tools                bookmark b1"
+        );
+    }
+
+    #[test]
+    fn test_report_table_no_changes() {
+        let mut report = SyncReport::new("github");
+        report.dry_run = true;
+        let table = report_table(&report);
+        assert_eq!(table.lines().nth(1), Some("no changes"));
+    }
+
+    #[test]
+    fn test_plugins_table() {
+        let plugins = vec![
+            PluginInfo {
+                name: "hn".to_string(),
+                list: "HN Upvoted".to_string(),
+                activated: true,
+                schedule: "@daily".to_string(),
+                last_success_at: Some(0),
+                failed_items: 2,
+            },
+            PluginInfo {
+                name: "github".to_string(),
+                list: "GitHub Starred".to_string(),
+                activated: false,
+                schedule: "@daily".to_string(),
+                last_success_at: None,
+                failed_items: 0,
+            },
+        ];
+
+        let table = plugins_table(&plugins);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "hn               HN Upvoted       yes        @daily       1970-01-01 00:00:00 UTC  2"
+        );
+        assert_eq!(
+            lines[2],
+            "github           GitHub Starred   no         @daily       never                    0"
+        );
+    }
+}
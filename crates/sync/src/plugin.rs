@@ -1,70 +1,587 @@
+mod gitea_stars;
+mod github_gists;
 mod github_stars;
+mod github_watching;
 mod hn_upvotes;
 mod pinboard;
 mod reddit_saves;
 
-use crate::karakeep;
+use crate::report::{PlannedAction, SyncReport};
+use crate::{karakeep, settings, state};
 use async_trait::async_trait;
 use futures::{Stream, StreamExt};
-use karakeep_client::BookmarkCreate;
+use karakeep_client::{BookmarkCreate, ListParent};
+use reqwest::Url;
+use std::collections::{BTreeSet, HashMap};
 use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// A page of bookmarks read from a plugin's source.
+#[derive(Debug, Default)]
+pub struct BookmarkChunk {
+    pub bookmarks: Vec<BookmarkCreate>,
+    /// Opaque position in the source right after this chunk, which the sync
+    /// checkpoints so an interrupted run can resume from it. `None` for the
+    /// last chunk or a source that can't be resumed. Incremental sources set
+    /// it on the last chunk too, and the next run continues from there.
+    pub cursor: Option<String>,
+}
+
+/// A list besides the plugin's own whose membership mirrors a list at the
+/// source, e.g. a GitHub star list.
+#[derive(Debug)]
+pub struct MirroredList {
+    pub name: String,
+    /// Name of a top level list to nest this one under.
+    pub parent: Option<String>,
+    pub bookmarks: Vec<BookmarkCreate>,
+}
+
+/// Chunks of a plugin's source. A failed fetch is yielded as an error, which
+/// stops the run and keeps the last checkpoint.
+pub type BookmarkStream = Pin<Box<dyn Stream<Item = anyhow::Result<BookmarkChunk>> + Send>>;
+
+/// Streams the pages of a paginated listing from `cursor` on, `fetch` gets the
+/// cursor of the page to fetch and returns it with the cursor of the next one.
+/// The stream ends after a page without a next one or after an error.
+fn paged_stream<F, Fut>(cursor: Option<String>, fetch: F) -> BookmarkStream
+where
+    F: Fn(Option<String>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<BookmarkChunk>> + Send + 'static,
+{
+    // the outer option ends the stream
+    let stream = futures::stream::unfold(Some(cursor), move |page| {
+        let chunk = page.map(&fetch);
+        async move {
+            match chunk?.await {
+                Ok(chunk) => {
+                    let next = chunk.cursor.clone().map(Some);
+                    Some((Ok(chunk), next))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    });
+    Box::pin(stream)
+}
 
 #[async_trait]
 pub trait Plugin: Send + Sync + 'static {
+    /// Stable identifier used to key this plugin's sync state.
+    fn name(&self) -> &'static str;
     fn list_name(&self) -> &'static str;
 
-    async fn to_bookmark_stream(
-        &self,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = Vec<BookmarkCreate>> + Send>>>;
+    /// Streams the source from the start, or from right after the chunk that
+    /// returned `cursor`.
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream>;
 
     fn is_activated(&self) -> bool;
     fn recurring_schedule(&self) -> String;
 
+    /// Verifies the plugin's credentials against its source.
+    async fn check(&self) -> anyhow::Result<()>;
+
+    /// Lists mirrored after the source was read to the end. Bookmarks that
+    /// left a list at the source are taken out of it, as long as this plugin
+    /// synced them.
+    async fn mirrored_lists(&self) -> anyhow::Result<Vec<MirroredList>> {
+        Ok(Vec::new())
+    }
+
     fn run_immediate(&self) -> bool {
         true
     }
 
-    async fn sync(&self) -> anyhow::Result<i32> {
-        let mut stream = self.to_bookmark_stream().await?;
+    async fn sync(&self, options: &SyncOptions) -> anyhow::Result<SyncReport> {
+        let started = Instant::now();
+        let name = self.name();
         let list_name = self.list_name();
+        let store = state::get_store();
 
-        let mut exists = 0;
-        let mut created_count = 0;
+        let checkpoint = store.checkpoint(name)?;
+        if let Some(cursor) = &checkpoint {
+            tracing::info!(
+                "continuing sync for list: {} from checkpoint {}",
+                list_name,
+                cursor
+            );
+        }
+        let mut stream = self.to_bookmark_stream(checkpoint).await?;
 
-        let client = karakeep::get_client();
-        let list_id = client.ensure_list_exists(list_name).await?;
+        let mut report = SyncReport::new(name);
+        report.dry_run = options.dry_run;
+
+        let list_id =
+            resolve_list(list_name, ListParent::Any, options.dry_run, &mut report).await?;
+
+        match store.last_success(name)? {
+            Some(ts) => tracing::info!("last successful sync for list: {} at {}", list_name, ts),
+            None => tracing::info!("first sync for list: {}", list_name),
+        }
+
+        let target = SyncTarget::new(name, list_name, list_id.as_deref(), options.dry_run);
+
+        let max_attempts = settings::get_settings().sync.maxattempts;
+        let retries = store.due_failures(name, max_attempts)?;
+        if !retries.is_empty() {
+            tracing::info!(
+                "retrying failed items for list: {} (count={})",
+                list_name,
+                retries.len()
+            );
+        }
+        for item in retries {
+            if options.cancel.is_cancelled() {
+                report.stopped_early = true;
+                break;
+            }
+            tracing::debug!(
+                "retrying bookmark '{}' (attempt {})",
+                &item.bookmark.url,
+                item.attempts + 1
+            );
+            report.retried += 1;
+            match target.sync_bookmark(&item.bookmark, &mut report).await {
+                Ok(()) if !target.dry_run => store.clear_failure(name, &item.source_id)?,
+                Ok(()) => {}
+                Err(e) => target.record_item_failure(&item.bookmark, &e, &mut report)?,
+            }
+        }
+
+        let mut last_cursor = false;
+        while !report.stopped_early {
+            let chunk = match next_chunk(&mut stream, &options.cancel, list_name, &mut report).await
+            {
+                Some(Some(chunk)) => chunk,
+                Some(None) => break,
+                None => {
+                    // the source was read to the end, the next run starts over
+                    // unless the source told us where to pick up
+                    if !options.dry_run && !last_cursor {
+                        store.clear_checkpoint(name)?;
+                    }
+                    break;
+                }
+            };
 
-        while let Some(chunk) = stream.next().await {
             tracing::info!(
                 "processing chunk for list: {} (count={})",
                 list_name,
-                chunk.len()
+                chunk.bookmarks.len()
             );
-            for bookmark in chunk {
-                let created = client.upsert_bookmark_to_list(&bookmark, &list_id).await?;
-                if created {
-                    exists = 0;
-                    created_count += 1;
-                } else {
-                    exists += 1;
+            for bookmark in chunk.bookmarks {
+                if options.cancel.is_cancelled() {
+                    report.stopped_early = true;
+                    break;
+                }
+
+                // items synced by a previous run are already in the list, and
+                // failed items are only picked up again once their backoff elapses
+                if store.bookmark_id(name, bookmark.source_id())?.is_some()
+                    || store.is_failed(name, bookmark.source_id())?
+                {
+                    report.skipped += 1;
+                    continue;
                 }
 
-                // if we have 5 consecutive existing posts, we can assume we've caught up
-                if exists >= 5 {
-                    tracing::info!("5 consecutive existing posts found, stopping sync");
-                    return Ok(created_count);
+                if let Err(e) = target.sync_bookmark(&bookmark, &mut report).await {
+                    target.record_item_failure(&bookmark, &e, &mut report)?;
                 }
             }
+
+            // a chunk cut short by a shutdown is walked again on resume, its
+            // synced items are skipped then
+            last_cursor = chunk.cursor.is_some();
+            if let Some(cursor) = chunk.cursor
+                && !report.stopped_early
+                && !options.dry_run
+            {
+                store.save_checkpoint(name, &cursor)?;
+            }
+        }
+
+        if !report.stopped_early {
+            let mirrored = match self.mirrored_lists().await {
+                Ok(lists) => mirror_lists(name, lists, options, &mut report).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = mirrored {
+                tracing::error!("failed to mirror lists of plugin: {}: {:#}", name, e);
+                report.stopped_early = true;
+            }
+        }
+
+        report.finish(started.elapsed());
+        if report.stopped_early {
+            tracing::warn!(
+                "sync for list: {} stopped early, the next run resumes from the last checkpoint",
+                list_name
+            );
+        }
+        if options.dry_run {
+            tracing::info!("dry run complete for list: {} ({})", list_name, report);
+            return Ok(report);
+        }
+
+        if !report.stopped_early {
+            store.record_success(name)?;
+        }
+        store.record_run(&report)?;
+
+        tracing::info!("sync complete for list: {} ({})", list_name, report);
+
+        Ok(report)
+    }
+}
+
+/// Pulls the next chunk from the source. `None` at the end of the stream,
+/// `Some(None)` if the run has to stop early, recorded in `report`: on
+/// shutdown or because the source failed.
+async fn next_chunk(
+    stream: &mut BookmarkStream,
+    cancel: &CancellationToken,
+    list_name: &str,
+    report: &mut SyncReport,
+) -> Option<Option<BookmarkChunk>> {
+    // stop pulling from the source on shutdown, but let the item that is
+    // being synced finish so it isn't left half-written
+    let chunk = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => {
+            report.stopped_early = true;
+            return Some(None);
         }
+        chunk = stream.next() => chunk,
+    };
+    match chunk? {
+        Ok(chunk) => Some(Some(chunk)),
+        Err(e) => {
+            tracing::error!("failed to read source of list: {}: {:#}", list_name, e);
+            report.record_source_error(&e);
+            Some(None)
+        }
+    }
+}
+
+/// Finds a list, creating it outside of dry runs. A dry run plans the creation
+/// of a missing list and returns `None` for it.
+async fn resolve_list(
+    name: &str,
+    parent: ListParent<'_>,
+    dry_run: bool,
+    report: &mut SyncReport,
+) -> anyhow::Result<Option<String>> {
+    let client = karakeep::get_client();
+    if !dry_run {
+        return Ok(Some(client.ensure_list_exists_in(name, parent).await?));
+    }
+
+    let list_id = client.find_list_in(name, parent).await?;
+    if list_id.is_none() {
+        report.actions.push(PlannedAction::CreateList {
+            list: name.to_string(),
+        });
+    }
+    Ok(list_id)
+}
+
+async fn mirror_lists(
+    plugin: &str,
+    lists: Vec<MirroredList>,
+    options: &SyncOptions,
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    let mut parents: HashMap<String, Option<String>> = HashMap::new();
+
+    for list in lists {
+        if options.cancel.is_cancelled() {
+            report.stopped_early = true;
+            break;
+        }
+
+        let list_id = match &list.parent {
+            // a mirrored list must not bind to a nested list that happens
+            // to have the same name, its stale bookmarks get removed
+            None => resolve_list(&list.name, ListParent::TopLevel, options.dry_run, report).await?,
+            Some(parent) => {
+                let parent_id = match parents.get(parent) {
+                    Some(id) => id.clone(),
+                    None => {
+                        let id =
+                            resolve_list(parent, ListParent::Any, options.dry_run, report).await?;
+                        parents.insert(parent.clone(), id.clone());
+                        id
+                    }
+                };
+                match parent_id {
+                    Some(parent_id) => {
+                        resolve_list(
+                            &list.name,
+                            ListParent::List(&parent_id),
+                            options.dry_run,
+                            report,
+                        )
+                        .await?
+                    }
+                    // a dry run for a parent that doesn't exist yet
+                    None => {
+                        report.actions.push(PlannedAction::CreateList {
+                            list: list.name.clone(),
+                        });
+                        None
+                    }
+                }
+            }
+        };
 
         tracing::info!(
-            "sync complete for list: {} (created={})",
-            list_name,
-            created_count
+            "mirroring list: {} (count={})",
+            list.name,
+            list.bookmarks.len()
         );
+        mirror_list(plugin, &list, list_id.as_deref(), options, report).await?;
+    }
 
-        Ok(created_count)
+    Ok(())
+}
+
+/// Adds the bookmarks of `list` to the Karakeep list and takes out the ones
+/// this plugin synced earlier that are no longer in it.
+async fn mirror_list(
+    plugin: &str,
+    list: &MirroredList,
+    list_id: Option<&str>,
+    options: &SyncOptions,
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    let client = karakeep::get_client();
+    let store = state::get_store();
+
+    let mut stale = match list_id {
+        Some(list_id) => client
+            .list_bookmark_ids(list_id)
+            .await?
+            .into_iter()
+            .collect::<BTreeSet<_>>(),
+        None => BTreeSet::new(),
+    };
+
+    let target = SyncTarget::new(plugin, &list.name, list_id, options.dry_run);
+    for bookmark in &list.bookmarks {
+        if options.cancel.is_cancelled() {
+            report.stopped_early = true;
+            return Ok(());
+        }
+
+        // the bookmarks were synced to the plugin's own list first, so most
+        // of them are known by now
+        if let Some(id) = store.bookmark_id(plugin, bookmark.source_id())?
+            && stale.remove(&id)
+        {
+            continue;
+        }
+
+        if let Err(e) = target.sync_bookmark(bookmark, report).await {
+            tracing::error!("failed to sync bookmark '{}': {:#}", &bookmark.url, e);
+            report.record_failure(&bookmark.url, &e);
+        } else if let Some(id) = store.bookmark_id(plugin, bookmark.source_id())? {
+            stale.remove(&id);
+        }
+    }
+
+    for bookmark_id in stale {
+        // leave bookmarks that were added to the list by hand
+        if !store.is_synced_bookmark(plugin, &bookmark_id)? {
+            continue;
+        }
+
+        if options.dry_run {
+            report.actions.push(PlannedAction::RemoveFromList {
+                list: list.name.clone(),
+                bookmark_id,
+            });
+        } else {
+            let list_id = list_id.expect("list exists outside of dry runs");
+            tracing::debug!("removing bookmark: {} from list: {}", &bookmark_id, list_id);
+            client
+                .remove_bookmark_from_list(&bookmark_id, list_id)
+                .await?;
+        }
+        report.removed_from_list += 1;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Only read from Karakeep and report the changes a sync would make.
+    pub dry_run: bool,
+    /// Cancelled on shutdown. The sync then finishes the item in progress,
+    /// records what it did so far and returns a report with `stopped_early`.
+    pub cancel: CancellationToken,
+}
+
+/// The list a sync run writes to. `list_id` is only `None` during a dry run
+/// for a list that does not exist yet.
+struct SyncTarget<'a> {
+    plugin: &'a str,
+    list_name: &'a str,
+    list_id: Option<&'a str>,
+    dry_run: bool,
+    /// IDs of the lists bookmarks asked for instead of `list_name`, by name.
+    other_lists: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl<'a> SyncTarget<'a> {
+    fn new(plugin: &'a str, list_name: &'a str, list_id: Option<&'a str>, dry_run: bool) -> Self {
+        Self {
+            plugin,
+            list_name,
+            list_id,
+            dry_run,
+            other_lists: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl SyncTarget<'_> {
+    /// Name and ID of the list `bookmark` goes to, the ID like `list_id`.
+    async fn list_for(
+        &self,
+        bookmark: &BookmarkCreate,
+        report: &mut SyncReport,
+    ) -> anyhow::Result<(String, Option<String>)> {
+        let Some(name) = bookmark
+            .list
+            .as_deref()
+            .filter(|name| *name != self.list_name)
+        else {
+            return Ok((self.list_name.to_string(), self.list_id.map(str::to_string)));
+        };
+
+        let cached = self.other_lists.lock().unwrap().get(name).cloned();
+        let list_id = match cached {
+            Some(list_id) => list_id,
+            None => {
+                let list_id = resolve_list(name, ListParent::Any, self.dry_run, report).await?;
+                self.other_lists
+                    .lock()
+                    .unwrap()
+                    .insert(name.to_string(), list_id.clone());
+                list_id
+            }
+        };
+        Ok((name.to_string(), list_id))
+    }
+
+    /// Makes sure `bookmark` exists in Karakeep and is in its list, recording
+    /// the outcome in the state store and `report`.
+    async fn sync_bookmark(
+        &self,
+        bookmark: &BookmarkCreate,
+        report: &mut SyncReport,
+    ) -> anyhow::Result<()> {
+        if bookmark.url.parse::<Url>().is_err() {
+            anyhow::bail!("invalid bookmark url: '{}'", bookmark.url);
+        }
+
+        let mut bookmark = bookmark.clone();
+        bookmark.set_source_plugin(self.plugin);
+        let bookmark = &bookmark;
+
+        let (list_name, list_id) = self.list_for(bookmark, report).await?;
+        if self.dry_run {
+            return plan_bookmark(bookmark, &list_name, list_id.as_deref(), report).await;
+        }
+
+        let list_id = list_id.expect("list exists outside of dry runs");
+        let result = karakeep::get_client()
+            .upsert_bookmark_to_list(bookmark, &list_id)
+            .await?;
+        state::get_store().record_item(self.plugin, bookmark.source_id(), &result.bookmark_id)?;
+
+        if result.created {
+            report.created += 1;
+        } else {
+            report.existing += 1;
+        }
+        if result.added_to_list {
+            report.added_to_list += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Records a failed item in `report` and queues it for a later retry.
+    fn record_item_failure(
+        &self,
+        bookmark: &BookmarkCreate,
+        error: &anyhow::Error,
+        report: &mut SyncReport,
+    ) -> anyhow::Result<()> {
+        tracing::error!("failed to sync bookmark '{}': {:#}", &bookmark.url, error);
+        report.record_failure(&bookmark.url, error);
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let attempts = state::get_store().record_failure(
+            self.plugin,
+            bookmark.source_id(),
+            bookmark,
+            &format!("{error:#}"),
+        )?;
+        if attempts >= settings::get_settings().sync.maxattempts {
+            tracing::error!(
+                "giving up on bookmark '{}' after {} attempts",
+                &bookmark.url,
+                attempts
+            );
+            report.dead_lettered += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read-only counterpart of `sync_bookmark` used by dry runs.
+async fn plan_bookmark(
+    bookmark: &BookmarkCreate,
+    list_name: &str,
+    list_id: Option<&str>,
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    let client = karakeep::get_client();
+
+    let Some(bookmark_id) = client.check_exists_bookmark(&bookmark.url).await? else {
+        report.created += 1;
+        report.added_to_list += 1;
+        report.actions.push(PlannedAction::CreateBookmark {
+            list: list_name.to_string(),
+            title: bookmark.title.clone(),
+            url: bookmark.url.clone(),
+        });
+        return Ok(());
+    };
+
+    report.existing += 1;
+    let in_list = match list_id {
+        Some(list_id) => client.is_bookmark_in_list(&bookmark_id, list_id).await?,
+        None => false,
+    };
+    if !in_list {
+        report.added_to_list += 1;
+        report.actions.push(PlannedAction::AddToList {
+            list: list_name.to_string(),
+            bookmark_id,
+            url: bookmark.url.clone(),
+        });
     }
+
+    Ok(())
 }
 
 pub fn get_plugins() -> Vec<Box<dyn Plugin>> {
@@ -72,6 +589,65 @@ pub fn get_plugins() -> Vec<Box<dyn Plugin>> {
         Box::new(hn_upvotes::HNUpvoted {}),
         Box::new(reddit_saves::RedditSaves {}),
         Box::new(github_stars::GithubStars {}),
+        Box::new(github_gists::GithubGists {}),
+        Box::new(github_watching::GithubWatching {}),
+        Box::new(gitea_stars::GiteaStars {}),
         Box::new(pinboard::PinboardBookmarks {}),
     ]
 }
+
+#[cfg(test)]
+mod test {
+    use super::{BookmarkChunk, BookmarkStream, next_chunk};
+    use crate::report::SyncReport;
+    use tokio_util::sync::CancellationToken;
+
+    fn chunk(cursor: &str) -> BookmarkChunk {
+        BookmarkChunk {
+            bookmarks: Vec::new(),
+            cursor: Some(cursor.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_source_error_fails_the_run() {
+        let mut stream: BookmarkStream = Box::pin(futures::stream::iter([
+            Err(anyhow::anyhow!("GitHub returned 502")),
+            Ok(chunk("?page=2")),
+        ]));
+        let mut report = SyncReport::new("github");
+
+        let chunk = next_chunk(
+            &mut stream,
+            &CancellationToken::new(),
+            "GitHub",
+            &mut report,
+        )
+        .await;
+        assert!(matches!(chunk, Some(None)));
+        assert!(report.stopped_early);
+        assert_eq!(report.source_error.as_deref(), Some("GitHub returned 502"));
+        assert!(!report.is_success());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_is_not_a_source_error() {
+        let mut stream: BookmarkStream = Box::pin(futures::stream::iter([Ok(chunk("?page=2"))]));
+        let cancel = CancellationToken::new();
+        let mut report = SyncReport::new("github");
+
+        let next = next_chunk(&mut stream, &cancel, "GitHub", &mut report).await;
+        assert_eq!(next.unwrap().unwrap().cursor.as_deref(), Some("?page=2"));
+        assert!(
+            next_chunk(&mut stream, &cancel, "GitHub", &mut report)
+                .await
+                .is_none()
+        );
+
+        cancel.cancel();
+        let next = next_chunk(&mut stream, &cancel, "GitHub", &mut report).await;
+        assert!(matches!(next, Some(None)));
+        assert!(report.stopped_early);
+        assert_eq!(report.source_error, None);
+    }
+}
@@ -0,0 +1,227 @@
+use super::github_stars::repo_bookmark;
+use super::{BookmarkChunk, BookmarkStream, paged_stream};
+use crate::settings;
+use async_trait::async_trait;
+use github_client::{Repo, parse_next_link};
+use reqwest::header;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct GiteaStars {}
+
+#[derive(Debug, Deserialize)]
+struct GiteaUser {
+    login: String,
+}
+
+/// Gitea and Forgejo share the same API.
+struct GiteaApi {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl GiteaApi {
+    fn new(base_url: &str, token: &str) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent("karakeep-sync/1.0")
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap();
+
+        Self {
+            client,
+            base_url: format!("{}/api/v1", base_url.trim_end_matches('/')),
+            token: token.to_string(),
+        }
+    }
+
+    fn from_settings() -> anyhow::Result<Self> {
+        let settings = &settings::get_settings().gitea;
+        let url = settings
+            .url
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Gitea URL is not set"))?;
+        let token = settings
+            .token
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Gitea token is not set"))?;
+        Ok(Self::new(url, token))
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<reqwest::Response> {
+        let resp = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .header(header::AUTHORIZATION, format!("token {}", self.token))
+            .header(header::ACCEPT, "application/json")
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Gitea {} returned {}", path, resp.status());
+        }
+        Ok(resp)
+    }
+
+    async fn user(&self) -> anyhow::Result<String> {
+        let resp = self.get("/user").await?;
+        Ok(resp.json::<GiteaUser>().await?.login)
+    }
+
+    /// One page of starred repos, `page` is the query string of the
+    /// `Link: rel="next"` of the previous one.
+    async fn starred(&self, page: Option<&str>) -> anyhow::Result<BookmarkChunk> {
+        tracing::info!("fetching Gitea stars page: {}", page.unwrap_or("?page=1"));
+        let resp = self
+            .get(&format!("/user/starred{}", page.unwrap_or("")))
+            .await?;
+
+        let next = resp
+            .headers()
+            .get(header::LINK)
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_next_link);
+        let repos = resp.json::<Vec<Repo>>().await?;
+
+        Ok(BookmarkChunk {
+            bookmarks: repos.into_iter().map(repo_bookmark).collect(),
+            cursor: next,
+        })
+    }
+
+    fn stars(self, cursor: Option<String>) -> BookmarkStream {
+        let api = Arc::new(self);
+        paged_stream(cursor, move |page| {
+            let api = api.clone();
+            async move { api.starred(page.as_deref()).await }
+        })
+    }
+}
+
+#[async_trait]
+impl super::Plugin for GiteaStars {
+    fn name(&self) -> &'static str {
+        "gitea"
+    }
+
+    fn list_name(&self) -> &'static str {
+        &settings::get_settings().gitea.list
+    }
+
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
+        // the cursor is the query string of the next page to fetch
+        Ok(GiteaApi::from_settings()?.stars(cursor))
+    }
+
+    fn is_activated(&self) -> bool {
+        let settings = &settings::get_settings().gitea;
+        settings.url.as_ref().is_some_and(|u| !u.is_empty())
+            && settings.token.as_ref().is_some_and(|t| !t.is_empty())
+    }
+
+    fn recurring_schedule(&self) -> String {
+        let settings = &settings::get_settings();
+        settings.gitea.schedule.clone()
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        GiteaApi::from_settings()?.user().await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GiteaApi;
+    use crate::plugin::BookmarkChunk;
+    use futures::TryStreamExt;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn repo_json(name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "full_name": name,
+            "html_url": format!("https://codeberg.org/{name}"),
+            "description": "A repo",
+            "topics": [],
+            "language": "Rust"
+        })
+    }
+
+    #[tokio::test]
+    async fn test_stars_follow_link_header() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/user/starred"))
+            .and(query_param("page", "2"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!([repo_json("b/b")])),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/user/starred"))
+            .and(header("authorization", "token TOKEN"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([repo_json("a/a")]))
+                    .insert_header(
+                        "link",
+                        format!(
+                            r#"<{}/api/v1/user/starred?limit=1&page=2>; rel="next""#,
+                            server.uri()
+                        ),
+                    ),
+            )
+            .mount(&server)
+            .await;
+
+        let chunks: Vec<BookmarkChunk> = GiteaApi::new(&server.uri(), "TOKEN")
+            .stars(None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].cursor.as_deref(), Some("?limit=1&page=2"));
+        assert_eq!(chunks[0].bookmarks[0].title, "a/a");
+        assert_eq!(
+            chunks[0].bookmarks[0].url,
+            "https://codeberg.org/a/a".to_string()
+        );
+        assert_eq!(chunks[0].bookmarks[0].note.as_deref(), Some("A repo"));
+        assert_eq!(chunks[0].bookmarks[0].tags, vec!["Rust"]);
+        assert_eq!(chunks[1].bookmarks[0].title, "b/b");
+        assert_eq!(chunks[1].cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_user() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/user"))
+            .and(header("authorization", "token TOKEN"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "login": "me" })),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/user"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let login = GiteaApi::new(&format!("{}/", server.uri()), "TOKEN")
+            .user()
+            .await
+            .unwrap();
+        assert_eq!(login, "me");
+
+        let err = GiteaApi::new(&server.uri(), "WRONG")
+            .user()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("401"));
+    }
+}
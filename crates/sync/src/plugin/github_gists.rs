@@ -0,0 +1,140 @@
+use super::github_stars::get_client;
+use super::{BookmarkChunk, BookmarkStream, paged_stream};
+use crate::settings;
+use async_trait::async_trait;
+use github_client::Gist;
+use karakeep_client::BookmarkCreate;
+
+#[derive(Debug, Clone)]
+pub struct GithubGists {}
+
+fn into_bookmark(gist: Gist) -> BookmarkCreate {
+    // gists without a description are named after their first file, like on
+    // gist.github.com
+    let title = match gist
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+    {
+        Some(description) => description.to_string(),
+        None => {
+            let file = gist
+                .files
+                .first()
+                .map(|f| f.filename.clone())
+                .unwrap_or_default();
+            match &gist.owner {
+                Some(owner) => format!("{}/{}", owner.login, file),
+                None => file,
+            }
+        }
+    };
+
+    let mut tags: Vec<String> = Vec::new();
+    for language in gist.files.into_iter().filter_map(|f| f.language) {
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(&language)) {
+            tags.push(language);
+        }
+    }
+
+    BookmarkCreate {
+        url: gist.html_url,
+        title,
+        tags,
+        ..Default::default()
+    }
+}
+
+async fn fetch_page(page: Option<String>) -> anyhow::Result<BookmarkChunk> {
+    tracing::info!(
+        "fetching GitHub starred gists page: {}",
+        page.as_deref().unwrap_or("?page=1")
+    );
+    let page = get_client()?.starred_gists(page.as_deref()).await?;
+
+    Ok(BookmarkChunk {
+        bookmarks: page.items.into_iter().map(into_bookmark).collect(),
+        cursor: page.next,
+    })
+}
+
+#[async_trait]
+impl super::Plugin for GithubGists {
+    fn name(&self) -> &'static str {
+        "github-gists"
+    }
+
+    fn list_name(&self) -> &'static str {
+        &settings::get_settings().github.gistslist
+    }
+
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
+        get_client()?;
+        Ok(paged_stream(cursor, fetch_page))
+    }
+
+    fn is_activated(&self) -> bool {
+        let settings = &settings::get_settings().github;
+        settings.gists && settings.token.as_ref().is_some_and(|t| !t.is_empty())
+    }
+
+    fn recurring_schedule(&self) -> String {
+        let settings = &settings::get_settings();
+        settings.github.schedule.clone()
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        get_client()?.user().await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::into_bookmark;
+    use github_client::Gist;
+
+    #[test]
+    fn test_gist_into_bookmark() {
+        let gist: Gist = serde_json::from_str(
+            r#"{
+                "html_url": "https://gist.github.com/abc",
+                "description": " Deploy script ",
+                "files": {
+                    "deploy.sh": { "language": "Shell" },
+                    "setup.sh": { "language": "Shell" },
+                    "notes.txt": { "language": null }
+                },
+                "owner": { "login": "octocat" }
+            }"#,
+        )
+        .unwrap();
+
+        let bookmark = into_bookmark(gist);
+        assert_eq!(bookmark.title, "Deploy script");
+        assert_eq!(bookmark.url, "https://gist.github.com/abc");
+        assert_eq!(bookmark.tags, vec!["Shell"]);
+    }
+
+    #[test]
+    fn test_untitled_gist_is_named_after_first_file() {
+        let gist: Gist = serde_json::from_str(
+            r#"{
+                "html_url": "https://gist.github.com/abc",
+                "description": "",
+                "files": {
+                    "main.rs": { "language": "Rust" },
+                    "README.md": { "language": "Markdown" },
+                    "Cargo.toml": { "language": "TOML" }
+                },
+                "owner": { "login": "octocat" }
+            }"#,
+        )
+        .unwrap();
+
+        let bookmark = into_bookmark(gist);
+        // GitHub's file order, not sorted by name
+        assert_eq!(bookmark.title, "octocat/main.rs");
+        assert_eq!(bookmark.tags, vec!["Rust", "Markdown", "TOML"]);
+    }
+}
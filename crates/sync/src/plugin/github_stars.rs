@@ -1,89 +1,94 @@
+use super::{BookmarkChunk, BookmarkStream, MirroredList, paged_stream};
 use crate::settings;
 use async_trait::async_trait;
-use futures::{Stream, stream};
-use karakeep_client::BookmarkCreate;
-use reqwest::Url;
-use std::pin::Pin;
+use github_client::{GitHubClient, Repo, StarredRepo};
+use karakeep_client::{BookmarkCreate, Timestamp};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone)]
 pub struct GithubStars {}
 
-fn parse_next_link(link_header: &str) -> Option<String> {
-    link_header
-        .split(',')
-        .find_map(|link| {
-            let parts: Vec<&str> = link.split(';').collect();
-            if parts.len() == 2 && parts[1].trim() == r#"rel="next""# {
-                let url: Url = parts[0]
-                    .trim()
-                    .trim_start_matches('<')
-                    .trim_end_matches('>')
-                    .parse()
-                    .ok()?;
-
-                let query = url.query()?;
-                Some(format!("?{query}"))
-            } else {
-                None
-            }
+/// Shared by the GitHub plugins for the lifetime of the process so unchanged
+/// pages can be revalidated with their ETag on the next run.
+pub(super) fn get_client() -> anyhow::Result<&'static GitHubClient> {
+    static CLIENT: OnceLock<GitHubClient> = OnceLock::new();
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+
+    let settings = &settings::get_settings().github;
+    let token = settings
+        .token
+        .clone()
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("GitHub token is not set"))?;
+    Ok(CLIENT.get_or_init(|| GitHubClient::new(token).with_base_url(&settings.url)))
+}
+
+fn into_bookmark(starred: StarredRepo) -> BookmarkCreate {
+    let created_at = Timestamp::parse_rfc3339(&starred.starred_at)
+        .inspect_err(|e| {
+            tracing::warn!(
+                "ignoring starred_at of GitHub repo '{}': {:#}",
+                starred.repo.full_name,
+                e
+            )
         })
-        .map(|s| s.to_string())
+        .ok();
+
+    BookmarkCreate {
+        created_at,
+        ..repo_bookmark(starred.repo)
+    }
+}
+
+/// Gitea and Forgejo describe repositories with the same fields as GitHub.
+pub(super) fn repo_bookmark(repo: Repo) -> BookmarkCreate {
+    let mut tags = repo.topics;
+    if let Some(language) = repo.language
+        && !tags.iter().any(|t| t.eq_ignore_ascii_case(&language))
+    {
+        tags.push(language);
+    }
+
+    BookmarkCreate {
+        url: repo.html_url,
+        title: repo.full_name,
+        tags,
+        note: repo
+            .description
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty()),
+        ..Default::default()
+    }
+}
+
+async fn fetch_page(page: Option<String>) -> anyhow::Result<BookmarkChunk> {
+    tracing::info!(
+        "fetching GitHub stars page: {}",
+        page.as_deref().unwrap_or("?page=1")
+    );
+    let page = get_client()?.starred(page.as_deref()).await?;
+
+    Ok(BookmarkChunk {
+        bookmarks: page.items.into_iter().map(into_bookmark).collect(),
+        cursor: page.next,
+    })
 }
 
 #[async_trait]
 impl super::Plugin for GithubStars {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
     fn list_name(&self) -> &'static str {
-        "GitHub Starred"
+        &settings::get_settings().github.list
     }
 
-    async fn to_bookmark_stream(
-        &self,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = Vec<BookmarkCreate>> + Send>>> {
-        let stream = stream::unfold(Some("?page=1".to_string()), move |params| async move {
-            let params = params?;
-
-            let settings = &settings::get_settings();
-            let token = settings
-                .github
-                .token
-                .as_ref()
-                .expect("GitHub token must be set for GitHub Stars plugin");
-
-            tracing::info!("fetching GitHub stars with params: {}", params);
-
-            let mut headers = reqwest::header::HeaderMap::new();
-            headers.insert("Authorization", format!("Bearer {token}").parse().unwrap());
-            headers.insert("User-Agent", "karakeep-sync/1.0".parse().unwrap());
-            headers.insert("Accept", "application/vnd.github.v3+json".parse().unwrap());
-
-            let client = reqwest::Client::new();
-            let url = format!("https://api.github.com/user/starred{params}");
-            let resp = client.get(url).headers(headers).send().await.ok()?;
-            if resp.status().is_success() {
-                tracing::info!("GitHub stars fetched successfully");
-            } else {
-                tracing::error!("GitHub stars fetch failed: {:?}", resp);
-            }
-
-            let next_page = resp.headers().get("Link").and_then(|link_header| {
-                let link_str = link_header.to_str().ok()?;
-                parse_next_link(link_str)
-            });
-
-            let resp = resp.json::<Vec<serde_json::Value>>().await.ok()?;
-            let bookmarks: Vec<BookmarkCreate> = resp
-                .into_iter()
-                .map(|item| BookmarkCreate {
-                    url: item["html_url"].as_str().unwrap_or("").to_string(),
-                    title: item["full_name"].as_str().unwrap_or("").to_string(),
-                    // GitHub does not provide timestamp for when the repo was starred
-                    created_at: None,
-                })
-                .collect();
-
-            Some((bookmarks, next_page))
-        });
-        Ok(Box::pin(stream))
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
+        get_client()?;
+        Ok(paged_stream(cursor, fetch_page))
     }
 
     fn is_activated(&self) -> bool {
@@ -95,24 +100,83 @@ impl super::Plugin for GithubStars {
         let settings = &settings::get_settings();
         settings.github.schedule.clone()
     }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        get_client()?.user().await.map(|_| ())
+    }
+
+    async fn mirrored_lists(&self) -> anyhow::Result<Vec<MirroredList>> {
+        let settings = &settings::get_settings().github;
+        if !settings.starlists {
+            return Ok(Vec::new());
+        }
+
+        let parent = settings.starlistsparent.clone().filter(|p| !p.is_empty());
+        let lists = get_client()?.star_lists().await?;
+        Ok(lists
+            .into_iter()
+            .map(|list| MirroredList {
+                name: list.name,
+                parent: parent.clone(),
+                bookmarks: list.repos.into_iter().map(repo_bookmark).collect(),
+            })
+            .collect())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::parse_next_link;
+    use super::into_bookmark;
+    use github_client::StarredRepo;
+
+    #[test]
+    fn test_starred_repo_into_bookmark() {
+        let starred: StarredRepo = serde_json::from_str(
+            r#"{
+                "starred_at": "2024-01-02T03:04:05Z",
+                "repo": {
+                    "full_name": "rust-lang/rust",
+                    "html_url": "https://github.com/rust-lang/rust",
+                    "description": "Empowering everyone to build reliable and efficient software. ",
+                    "topics": ["compiler", "rust"],
+                    "language": "Rust"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let bookmark = into_bookmark(starred);
+        assert_eq!(bookmark.title, "rust-lang/rust");
+        assert_eq!(bookmark.url, "https://github.com/rust-lang/rust");
+        assert_eq!(
+            bookmark.created_at.unwrap().to_string(),
+            "2024-01-02T03:04:05.000Z"
+        );
+        assert_eq!(
+            bookmark.note.as_deref(),
+            Some("Empowering everyone to build reliable and efficient software.")
+        );
+        // the language is already a topic
+        assert_eq!(bookmark.tags, vec!["compiler", "rust"]);
+    }
 
     #[test]
-    fn test_parse_next_link() {
-        let link_header = r#"<https://api.github.com/user/starred?page=2>; rel="next", <https://api.github.com/user/starred?page=34>; rel="last""#;
-        let next_link = parse_next_link(link_header);
-        assert_eq!(next_link, Some("?page=2".to_string()));
-
-        let link_header_no_next = r#"<https://api.github.com/user/starred?page=34>; rel="last""#;
-        let next_link = parse_next_link(link_header_no_next);
-        assert_eq!(next_link, None);
-
-        let empty_link_header = "";
-        let next_link = parse_next_link(empty_link_header);
-        assert_eq!(next_link, None);
+    fn test_starred_repo_without_metadata() {
+        let starred: StarredRepo = serde_json::from_str(
+            r#"{
+                "starred_at": "2024-01-02T03:04:05Z",
+                "repo": {
+                    "full_name": "octocat/hello-world",
+                    "html_url": "https://github.com/octocat/hello-world",
+                    "description": null,
+                    "language": "Go"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let bookmark = into_bookmark(starred);
+        assert_eq!(bookmark.note, None);
+        assert_eq!(bookmark.tags, vec!["Go"]);
     }
 }
@@ -0,0 +1,50 @@
+use super::github_stars::{get_client, repo_bookmark};
+use super::{BookmarkChunk, BookmarkStream, paged_stream};
+use crate::settings;
+use async_trait::async_trait;
+
+#[derive(Debug, Clone)]
+pub struct GithubWatching {}
+
+async fn fetch_page(page: Option<String>) -> anyhow::Result<BookmarkChunk> {
+    tracing::info!(
+        "fetching GitHub watched repos page: {}",
+        page.as_deref().unwrap_or("?page=1")
+    );
+    let page = get_client()?.subscriptions(page.as_deref()).await?;
+
+    Ok(BookmarkChunk {
+        bookmarks: page.items.into_iter().map(repo_bookmark).collect(),
+        cursor: page.next,
+    })
+}
+
+#[async_trait]
+impl super::Plugin for GithubWatching {
+    fn name(&self) -> &'static str {
+        "github-watching"
+    }
+
+    fn list_name(&self) -> &'static str {
+        &settings::get_settings().github.watchedlist
+    }
+
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
+        get_client()?;
+        Ok(paged_stream(cursor, fetch_page))
+    }
+
+    fn is_activated(&self) -> bool {
+        let settings = &settings::get_settings().github;
+        settings.watched && settings.token.as_ref().is_some_and(|t| !t.is_empty())
+    }
+
+    fn recurring_schedule(&self) -> String {
+        let settings = &settings::get_settings();
+        settings.github.schedule.clone()
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        get_client()?.user().await.map(|_| ())
+    }
+}
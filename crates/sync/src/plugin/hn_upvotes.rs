@@ -1,9 +1,9 @@
+use super::{BookmarkChunk, BookmarkStream};
 use crate::settings;
 use async_trait::async_trait;
-use futures::{Stream, StreamExt};
+use futures::StreamExt;
 use hnscraper::stream_pages;
 use karakeep_client::BookmarkCreate;
-use std::pin::Pin;
 
 #[derive(Debug, Clone)]
 pub struct HNUpvoted {}
@@ -14,13 +14,15 @@ fn extract_username_from_auth(hn_auth: &str) -> Option<String> {
 
 #[async_trait]
 impl super::Plugin for HNUpvoted {
+    fn name(&self) -> &'static str {
+        "hn"
+    }
+
     fn list_name(&self) -> &'static str {
         "HN Upvoted"
     }
 
-    async fn to_bookmark_stream(
-        &self,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = Vec<BookmarkCreate>> + Send>>> {
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
         let settings = settings::get_settings();
         let auth = &settings
             .hn
@@ -30,17 +32,26 @@ impl super::Plugin for HNUpvoted {
 
         let username = extract_username_from_auth(auth)
             .ok_or_else(|| anyhow::anyhow!("Failed to extract username from auth token"))?;
-        let start_path = format!("upvoted?id={username}");
+        // the cursor is the path of the next page of upvotes
+        let start_path = cursor.unwrap_or_else(|| format!("upvoted?id={username}"));
 
         let stream = stream_pages(auth, start_path)?.map(|page| {
-            page.into_iter()
+            let page = page?;
+            let bookmarks = page
+                .posts
+                .into_iter()
                 .map(|post| BookmarkCreate {
                     title: post.title,
                     url: post.url,
                     // HN does not provide timestamp for when the post was upvoted
                     created_at: None,
+                    ..Default::default()
                 })
-                .collect::<Vec<_>>()
+                .collect::<Vec<_>>();
+            Ok(BookmarkChunk {
+                bookmarks,
+                cursor: page.next_path,
+            })
         });
 
         Ok(Box::pin(stream))
@@ -55,4 +66,15 @@ impl super::Plugin for HNUpvoted {
         let settings = &settings::get_settings();
         settings.hn.schedule.clone()
     }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        let settings = settings::get_settings();
+        let auth = settings
+            .hn
+            .auth
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("HN auth token is not set"))?;
+
+        hnscraper::check_auth(auth).await
+    }
 }
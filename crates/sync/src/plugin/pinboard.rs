@@ -1,68 +1,350 @@
+use super::{BookmarkChunk, BookmarkStream};
 use crate::settings;
 use async_trait::async_trait;
-use futures::{Stream, stream};
-use karakeep_client::BookmarkCreate;
+use futures::{StreamExt, stream};
+use karakeep_client::{BookmarkCreate, BookmarkSource, Timestamp};
+use reqwest::StatusCode;
 use serde::Deserialize;
-use std::pin::Pin;
+use std::time::Duration;
+
+const PINBOARD_API_URL: &str = "https://api.pinboard.in/v1";
+/// Retries of a rate limited request before giving up on it.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+/// Pinboard allows one call every 3 seconds, and one every 5 minutes for
+/// `posts/all`. A 429 without `Retry-After` waits out the method's interval.
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(3);
+const POSTS_ALL_RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// `posts/all` streams every bookmark of the account, so only a stalled
+/// connection times out, not a long download.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Bookmarks per chunk when streaming `posts/all`.
+const CHUNK_SIZE: usize = 200;
 
 #[derive(Debug, Clone)]
 pub struct PinboardBookmarks {}
 
+#[derive(Debug, Deserialize)]
+struct UpdateResponse {
+    update_time: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct PinboardPost {
     href: String,
     description: String,
-    #[allow(dead_code)]
     extended: String,
-    #[allow(dead_code)]
     hash: String,
-    #[allow(dead_code)]
     time: String,
-    #[allow(dead_code)]
+    /// Space separated.
     tags: String,
+    /// "yes" or "no".
+    #[serde(default)]
+    shared: String,
+    /// "yes" or "no".
+    #[serde(default)]
+    toread: String,
 }
 
-#[async_trait]
-impl super::Plugin for PinboardBookmarks {
-    fn list_name(&self) -> &'static str {
-        "Pinboard"
+impl PinboardPost {
+    fn into_bookmark(self) -> BookmarkCreate {
+        let created_at = Timestamp::parse_rfc3339(&self.time)
+            .inspect_err(|e| {
+                tracing::warn!(
+                    "ignoring time of Pinboard bookmark '{}': {:#}",
+                    self.href,
+                    e
+                )
+            })
+            .ok();
+        let extended = self.extended.trim();
+        BookmarkCreate {
+            url: self.href,
+            title: self.description,
+            created_at,
+            tags: self.tags.split_whitespace().map(str::to_string).collect(),
+            note: (!extended.is_empty()).then(|| extended.to_string()),
+            // bookmarks still marked "to read" stay in the Karakeep inbox.
+            // Karakeep only takes the flags when the bookmark is created, an
+            // existing bookmark for the same URL keeps its own
+            archived: self.toread != "yes",
+            favourited: self.shared == "yes",
+            source: Some(BookmarkSource::new(self.hash)),
+            ..Default::default()
+        }
     }
+}
 
-    async fn to_bookmark_stream(
-        &self,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = Vec<BookmarkCreate>> + Send>>> {
+fn rate_limit_interval(method: &str) -> Duration {
+    if method == "posts/all" {
+        POSTS_ALL_RATE_LIMIT_INTERVAL
+    } else {
+        RATE_LIMIT_INTERVAL
+    }
+}
+
+struct PinboardApi {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl PinboardApi {
+    fn new(base_url: &str, token: &str) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent("karakeep-sync/1.0")
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .unwrap();
+        Self {
+            client,
+            base_url: base_url.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    fn from_settings() -> anyhow::Result<Self> {
         let settings = &settings::get_settings();
         let token = settings
             .pinboard
             .token
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Pinboard token is not set"))?;
+        Ok(Self::new(PINBOARD_API_URL, token))
+    }
+
+    /// GETs a Pinboard API method, waiting and retrying when rate limited.
+    async fn get(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+    ) -> anyhow::Result<reqwest::Response> {
+        let url = format!("{}/{}", self.base_url, method);
+        let interval = rate_limit_interval(method);
 
-        tracing::info!("fetching Pinboard bookmarks");
+        for attempt in 0..=MAX_RATE_LIMIT_RETRIES {
+            let resp = self
+                .client
+                .get(&url)
+                .query(&[("auth_token", self.token.as_str()), ("format", "json")])
+                .query(params)
+                .send()
+                .await
+                .map_err(|e| e.without_url())?;
 
-        let client = reqwest::Client::new();
-        let url = format!("https://api.pinboard.in/v1/posts/all?auth_token={token}&format=json");
-        let resp = client.get(&url).send().await?;
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                if !resp.status().is_success() {
+                    anyhow::bail!("Pinboard {} returned {}", method, resp.status());
+                }
+                return Ok(resp);
+            }
+            if attempt == MAX_RATE_LIMIT_RETRIES {
+                break;
+            }
 
-        if !resp.status().is_success() {
-            anyhow::bail!("Failed to fetch Pinboard bookmarks: {}", resp.status());
+            let wait = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(interval);
+            tracing::warn!(
+                "Pinboard {} is rate limited, retrying in {:?}",
+                method,
+                wait
+            );
+            tokio::time::sleep(wait).await;
         }
 
-        let posts = resp.json::<Vec<PinboardPost>>().await?;
-        let bookmarks: Vec<BookmarkCreate> = posts
-            .into_iter()
-            .map(|post| BookmarkCreate {
-                url: post.href,
-                title: post.description,
-                created_at: Some(post.time),
-            })
-            .collect();
+        anyhow::bail!(
+            "Pinboard {} is still rate limited after {} retries",
+            method,
+            MAX_RATE_LIMIT_RETRIES
+        )
+    }
 
-        tracing::info!("fetched {} Pinboard bookmarks", bookmarks.len());
+    /// When bookmarks were last added, changed or deleted.
+    async fn update_time(&self) -> anyhow::Result<String> {
+        let resp = self.get("posts/update", &[]).await?;
+        Ok(resp.json::<UpdateResponse>().await?.update_time)
+    }
 
-        // Return all bookmarks as a single chunk since Pinboard returns everything at once
-        let stream = stream::once(async move { bookmarks });
-        Ok(Box::pin(stream))
+    /// Bookmarks added since the `posts/update` time `since` of a previous
+    /// run, or all bookmarks without one. The last chunk carries the current
+    /// update time as its cursor for the next run.
+    async fn changes(&self, since: Option<&str>) -> anyhow::Result<BookmarkStream> {
+        let update_time = self.update_time().await?;
+        if since == Some(update_time.as_str()) {
+            tracing::info!("no Pinboard changes since {}", update_time);
+            let chunk = BookmarkChunk {
+                bookmarks: Vec::new(),
+                cursor: Some(update_time),
+            };
+            return Ok(Box::pin(stream::once(async move { Ok(chunk) })));
+        }
+
+        match since {
+            Some(since) => tracing::info!("fetching Pinboard bookmarks added since {}", since),
+            None => tracing::info!("fetching all Pinboard bookmarks"),
+        }
+        let params = since.map(|dt| ("fromdt", dt));
+        let resp = self.get("posts/all", params.as_slice()).await?;
+
+        Ok(post_chunks(resp, since.map(str::to_string), update_time))
+    }
+}
+
+/// Parses the `posts/all` response as it arrives and emits its bookmarks in
+/// chunks of `CHUNK_SIZE`, so memory does not grow with the account size.
+///
+/// A `posts/all` download can't be resumed halfway, so all but the last chunk
+/// keep the previous cursor and an interrupted run downloads it again.
+fn post_chunks(
+    resp: reqwest::Response,
+    since: Option<String>,
+    update_time: String,
+) -> BookmarkStream {
+    let body = Box::pin(resp.bytes_stream());
+    let state = (body, JsonArraySplitter::default(), Vec::new());
+
+    let stream = stream::unfold(Some(state), move |state| {
+        let since = since.clone();
+        let update_time = update_time.clone();
+        async move {
+            let (mut body, mut splitter, mut bookmarks) = state?;
+            loop {
+                if bookmarks.len() >= CHUNK_SIZE {
+                    let rest = bookmarks.split_off(CHUNK_SIZE);
+                    let chunk = BookmarkChunk {
+                        bookmarks,
+                        cursor: since,
+                    };
+                    return Some((Ok(chunk), Some((body, splitter, rest))));
+                }
+
+                let result = match body.next().await {
+                    Some(Ok(bytes)) => splitter.push(&bytes).and_then(|posts| {
+                        for post in posts {
+                            let post = serde_json::from_slice::<PinboardPost>(&post)?;
+                            bookmarks.push(post.into_bookmark());
+                        }
+                        Ok(())
+                    }),
+                    Some(Err(e)) => Err(e.without_url().into()),
+                    None => {
+                        let chunk = splitter.finish().map(|_| BookmarkChunk {
+                            bookmarks,
+                            cursor: Some(update_time),
+                        });
+                        return Some((chunk, None));
+                    }
+                };
+                if let Err(e) = result {
+                    let e = e.context("failed to read Pinboard bookmarks");
+                    return Some((Err(e), None));
+                }
+            }
+        }
+    });
+    Box::pin(stream)
+}
+
+/// Splits a JSON array of objects into the raw bytes of its elements while
+/// it is read, keeping only the element currently being read in memory.
+#[derive(Debug, Default)]
+struct JsonArraySplitter {
+    started: bool,
+    finished: bool,
+    /// Nesting level, 1 between elements of the array.
+    depth: u32,
+    in_string: bool,
+    escaped: bool,
+    element: Vec<u8>,
+}
+
+impl JsonArraySplitter {
+    /// Feeds the next bytes of the document and returns the elements they
+    /// completed.
+    fn push(&mut self, bytes: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut elements = Vec::new();
+        for &b in bytes {
+            if !self.started {
+                match b {
+                    b'[' => {
+                        self.started = true;
+                        self.depth = 1;
+                    }
+                    b if b.is_ascii_whitespace() => {}
+                    _ => anyhow::bail!("expected a JSON array"),
+                }
+            } else if self.finished {
+                if !b.is_ascii_whitespace() {
+                    anyhow::bail!("unexpected data after the JSON array");
+                }
+            } else if self.element.is_empty() {
+                match b {
+                    b'{' | b'[' => {
+                        self.element.push(b);
+                        self.depth += 1;
+                    }
+                    b']' => self.finished = true,
+                    b',' => {}
+                    b if b.is_ascii_whitespace() => {}
+                    _ => anyhow::bail!("expected a JSON object in the array"),
+                }
+            } else {
+                self.element.push(b);
+                if self.in_string {
+                    match b {
+                        _ if self.escaped => self.escaped = false,
+                        b'\\' => self.escaped = true,
+                        b'"' => self.in_string = false,
+                        _ => {}
+                    }
+                    continue;
+                }
+                match b {
+                    b'"' => self.in_string = true,
+                    b'{' | b'[' => self.depth += 1,
+                    b'}' | b']' => {
+                        self.depth -= 1;
+                        if self.depth == 1 {
+                            elements.push(std::mem::take(&mut self.element));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Fails if the document ended before the array was closed.
+    fn finish(&self) -> anyhow::Result<()> {
+        if !self.finished {
+            anyhow::bail!("JSON array ended unexpectedly");
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl super::Plugin for PinboardBookmarks {
+    fn name(&self) -> &'static str {
+        "pinboard"
+    }
+
+    fn list_name(&self) -> &'static str {
+        "Pinboard"
+    }
+
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
+        // the cursor is the `posts/update` time of the last completed run
+        PinboardApi::from_settings()?
+            .changes(cursor.as_deref())
+            .await
     }
 
     fn is_activated(&self) -> bool {
@@ -74,4 +356,295 @@ impl super::Plugin for PinboardBookmarks {
         let settings = &settings::get_settings();
         settings.pinboard.schedule.clone()
     }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        PinboardApi::from_settings()?
+            .update_time()
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CHUNK_SIZE, JsonArraySplitter, POSTS_ALL_RATE_LIMIT_INTERVAL, PinboardApi, PinboardPost,
+        RATE_LIMIT_INTERVAL, rate_limit_interval,
+    };
+    use crate::plugin::BookmarkChunk;
+    use futures::TryStreamExt;
+    use wiremock::matchers::{method, path, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn changes(api: &PinboardApi, since: Option<&str>) -> Vec<BookmarkChunk> {
+        api.changes(since)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    fn post_json(hash: &str) -> serde_json::Value {
+        serde_json::json!({
+            "href": format!("https://example.com/{hash}"),
+            "description": "Example",
+            "extended": "",
+            "hash": hash,
+            "time": "2024-01-02T03:04:05Z",
+            "shared": "no",
+            "toread": "no",
+            "tags": ""
+        })
+    }
+
+    async fn mock_update_time(server: &MockServer, update_time: &str) {
+        Mock::given(method("GET"))
+            .and(path("/posts/update"))
+            .and(query_param("auth_token", "user:TOKEN"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "update_time": update_time })),
+            )
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_changes_fetches_all_posts_on_first_run() {
+        let server = MockServer::start().await;
+        mock_update_time(&server, "2024-02-01T00:00:00Z").await;
+        Mock::given(method("GET"))
+            .and(path("/posts/all"))
+            .and(query_param_is_missing("fromdt"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([post_json("a"), post_json("b")])),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let api = PinboardApi::new(&server.uri(), "user:TOKEN");
+        let chunks = changes(&api, None).await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].bookmarks.len(), 2);
+        assert_eq!(chunks[0].cursor.as_deref(), Some("2024-02-01T00:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn test_changes_skips_unchanged_account() {
+        let server = MockServer::start().await;
+        mock_update_time(&server, "2024-02-01T00:00:00Z").await;
+        Mock::given(method("GET"))
+            .and(path("/posts/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let api = PinboardApi::new(&server.uri(), "user:TOKEN");
+        let chunks = changes(&api, Some("2024-02-01T00:00:00Z")).await;
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].bookmarks.is_empty());
+        assert_eq!(chunks[0].cursor.as_deref(), Some("2024-02-01T00:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn test_changes_fetches_newer_posts_and_retries_rate_limits() {
+        let server = MockServer::start().await;
+        mock_update_time(&server, "2024-03-01T00:00:00Z").await;
+        Mock::given(method("GET"))
+            .and(path("/posts/all"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/posts/all"))
+            .and(query_param("fromdt", "2024-02-01T00:00:00Z"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!([post_json("c")])),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let api = PinboardApi::new(&server.uri(), "user:TOKEN");
+        let chunks = changes(&api, Some("2024-02-01T00:00:00Z")).await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].bookmarks.len(), 1);
+        assert_eq!(chunks[0].bookmarks[0].source_id(), "c");
+        assert_eq!(chunks[0].cursor.as_deref(), Some("2024-03-01T00:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn test_changes_emits_bounded_chunks() {
+        let server = MockServer::start().await;
+        mock_update_time(&server, "2024-03-01T00:00:00Z").await;
+        let posts = (0..CHUNK_SIZE * 2 + 1)
+            .map(|i| post_json(&i.to_string()))
+            .collect::<Vec<_>>();
+        Mock::given(method("GET"))
+            .and(path("/posts/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(posts))
+            .mount(&server)
+            .await;
+
+        let api = PinboardApi::new(&server.uri(), "user:TOKEN");
+        let chunks = changes(&api, Some("2024-02-01T00:00:00Z")).await;
+        let sizes = chunks.iter().map(|c| c.bookmarks.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![CHUNK_SIZE, CHUNK_SIZE, 1]);
+
+        // only the last chunk moves the cursor on to the new update time
+        assert_eq!(chunks[0].cursor.as_deref(), Some("2024-02-01T00:00:00Z"));
+        assert_eq!(chunks[2].cursor.as_deref(), Some("2024-03-01T00:00:00Z"));
+        assert_eq!(
+            chunks[2].bookmarks[0].source_id(),
+            (CHUNK_SIZE * 2).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes_fails_on_truncated_response() {
+        let server = MockServer::start().await;
+        mock_update_time(&server, "2024-03-01T00:00:00Z").await;
+        let body = format!("[{},{{\"href\":", post_json("a"));
+        Mock::given(method("GET"))
+            .and(path("/posts/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let api = PinboardApi::new(&server.uri(), "user:TOKEN");
+        let result = api
+            .changes(None)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_json_array_splitter() {
+        let doc = br#" [ {"a": "}]\"{[", "b": [1, {"c": 2}]}, {"d": null} ] "#;
+
+        // elements come out the same however the document is split up
+        for split in 1..doc.len() {
+            let mut splitter = JsonArraySplitter::default();
+            let mut elements = Vec::new();
+            for bytes in doc.chunks(split) {
+                elements.extend(splitter.push(bytes).unwrap());
+            }
+            splitter.finish().unwrap();
+
+            let elements = elements
+                .iter()
+                .map(|e| serde_json::from_slice::<serde_json::Value>(e).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                elements,
+                vec![
+                    serde_json::json!({"a": "}]\"{[", "b": [1, {"c": 2}]}),
+                    serde_json::json!({"d": null}),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_array_splitter_rejects_invalid_documents() {
+        let mut splitter = JsonArraySplitter::default();
+        assert!(splitter.push(b"[]").unwrap().is_empty());
+        assert!(splitter.finish().is_ok());
+
+        assert!(JsonArraySplitter::default().push(br#"{"a": 1}"#).is_err());
+        assert!(JsonArraySplitter::default().push(b"[1, 2]").is_err());
+        assert!(JsonArraySplitter::default().push(b"[] []").is_err());
+
+        let mut splitter = JsonArraySplitter::default();
+        splitter.push(br#"[{"a": 1}"#).unwrap();
+        assert!(splitter.finish().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/posts/update"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .mount(&server)
+            .await;
+
+        let api = PinboardApi::new(&server.uri(), "user:TOKEN");
+        let err = api.update_time().await.unwrap_err();
+        assert!(format!("{err:#}").contains("rate limited"));
+    }
+
+    #[test]
+    fn test_rate_limit_interval() {
+        // posts/all may only be called every 5 minutes, a shorter backoff
+        // can't outlast it
+        assert_eq!(
+            rate_limit_interval("posts/all"),
+            POSTS_ALL_RATE_LIMIT_INTERVAL
+        );
+        assert_eq!(rate_limit_interval("posts/update"), RATE_LIMIT_INTERVAL);
+    }
+
+    #[test]
+    fn test_into_bookmark() {
+        let post: PinboardPost = serde_json::from_str(
+            r#"{
+                "href": "https://example.com/",
+                "description": "Example",
+                "extended": "  worth a read ",
+                "meta": "ignored",
+                "hash": "a5c0e2d3",
+                "time": "2024-01-02T03:04:05Z",
+                "shared": "yes",
+                "toread": "yes",
+                "tags": "rust  cli"
+            }"#,
+        )
+        .unwrap();
+
+        let bookmark = post.into_bookmark();
+        assert_eq!(bookmark.title, "Example");
+        assert_eq!(
+            bookmark.created_at.unwrap().to_string(),
+            "2024-01-02T03:04:05.000Z"
+        );
+        assert_eq!(bookmark.tags, vec!["rust", "cli"]);
+        assert_eq!(bookmark.note.as_deref(), Some("worth a read"));
+        assert!(!bookmark.archived);
+        assert!(bookmark.favourited);
+        assert_eq!(bookmark.source_id(), "a5c0e2d3");
+    }
+
+    #[test]
+    fn test_into_bookmark_without_metadata() {
+        let post: PinboardPost = serde_json::from_str(
+            r#"{
+                "href": "https://example.com/",
+                "description": "Example",
+                "extended": "",
+                "hash": "a5c0e2d3",
+                "time": "yesterday",
+                "shared": "no",
+                "toread": "no",
+                "tags": ""
+            }"#,
+        )
+        .unwrap();
+
+        let bookmark = post.into_bookmark();
+        assert_eq!(bookmark.created_at, None);
+        assert!(bookmark.tags.is_empty());
+        assert_eq!(bookmark.note, None);
+        assert!(bookmark.archived);
+        assert!(!bookmark.favourited);
+    }
 }
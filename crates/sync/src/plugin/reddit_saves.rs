@@ -1,96 +1,164 @@
 use anyhow::Context;
 use async_trait::async_trait;
-use futures::Stream;
-use karakeep_client::BookmarkCreate;
-use std::{pin::Pin, sync::Arc};
+use karakeep_client::{BookmarkCreate, BookmarkSource, Timestamp};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::settings;
-use reddit_client::RedditClientRefresher;
+use super::{BookmarkChunk, BookmarkStream, paged_stream};
+
+use crate::metrics;
+use crate::settings::{self, RedditLinks, RedditNsfw, RedditSettings};
+use reddit_client::{RedditClient, RedditClientRefresher, SavedKind, SavedPost};
 
 #[derive(Debug, Clone)]
 pub struct RedditSaves {}
 
+/// Maps a saved item to a bookmark, or `None` if the settings skip it.
+fn into_bookmark(post: SavedPost, settings: &RedditSettings) -> Option<BookmarkCreate> {
+    if post.kind == SavedKind::Comment && !settings.comments {
+        return None;
+    }
+    if post.over_18 && settings.nsfw == RedditNsfw::Skip {
+        return None;
+    }
+
+    // a saved comment keeps what was said and by whom, the title is the
+    // submission it was made on
+    let note = match post.kind {
+        SavedKind::Post => None,
+        SavedKind::Comment => {
+            let body = post.body.as_deref().unwrap_or_default().trim();
+            match &post.author {
+                Some(author) => Some(format!("{body}\n\n— u/{author}")),
+                None => Some(body.to_string()),
+            }
+            .filter(|note| !note.is_empty())
+        }
+    };
+
+    let (url, note) = match (post.link, settings.links) {
+        (Some(link), RedditLinks::Article) => (link, note),
+        (Some(link), RedditLinks::Both) => (link, Some(format!("Discussion: {}", post.url))),
+        _ => (post.url, note),
+    };
+
+    // Reddit does not say when an item was saved, so it is dated by when it
+    // was posted
+    let created_at = post.created_utc.and_then(|secs| {
+        Timestamp::from_unix_secs(secs)
+            .inspect_err(|e| {
+                tracing::warn!("ignoring created_utc of Reddit item '{}': {:#}", url, e)
+            })
+            .ok()
+    });
+
+    let mut tags = Vec::new();
+    if let Some(subreddit) = post.subreddit {
+        tags.push(format!("r/{subreddit}"));
+    }
+    tags.extend(post.flair);
+    let mut list = None;
+    if post.over_18 {
+        match settings.nsfw {
+            RedditNsfw::Tag => tags.push("nsfw".to_string()),
+            RedditNsfw::List => list = Some(settings.nsfwlist.clone()),
+            RedditNsfw::Skip => {}
+        }
+    }
+
+    Some(BookmarkCreate {
+        title: post.title,
+        url,
+        created_at,
+        tags,
+        note,
+        list,
+        source: post.fullname.map(BookmarkSource::new),
+        ..Default::default()
+    })
+}
+
+async fn authenticate() -> anyhow::Result<RedditClient> {
+    let settings = settings::get_settings();
+    let client_id = settings
+        .reddit
+        .clientid
+        .as_ref()
+        .context("Reddit client ID is not set")?
+        .clone();
+    let client_secret = settings
+        .reddit
+        .clientsecret
+        .as_ref()
+        .context("Reddit client secret is not set")?
+        .clone();
+    let refresh_token = settings
+        .reddit
+        .refreshtoken
+        .as_ref()
+        .context("Reddit refresh token is not set")?
+        .clone();
+    let username = settings
+        .reddit
+        .username
+        .as_ref()
+        .context("Reddit username is not set")?
+        .clone();
+
+    RedditClientRefresher::new(client_id, client_secret, refresh_token)
+        .refresh(username)
+        .await
+}
+
 #[async_trait]
 impl super::Plugin for RedditSaves {
+    fn name(&self) -> &'static str {
+        "reddit"
+    }
+
     fn list_name(&self) -> &'static str {
         "Reddit Saved"
     }
 
-    async fn to_bookmark_stream(
-        &self,
-    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = Vec<BookmarkCreate>> + Send>>> {
-        let settings = settings::get_settings();
-        let client_id = settings
-            .reddit
-            .clientid
-            .as_ref()
-            .context("Reddit client ID is not set")?
-            .clone();
-        let client_secret = settings
-            .reddit
-            .clientsecret
-            .as_ref()
-            .context("Reddit client secret is not set")?
-            .clone();
-        let refresh_token = settings
-            .reddit
-            .refreshtoken
-            .as_ref()
-            .context("Reddit refresh token is not set")?
-            .clone();
-        let username = settings
-            .reddit
-            .username
-            .as_ref()
-            .context("Reddit username is not set")?
-            .clone();
-
-        let client = RedditClientRefresher::new(client_id, client_secret, refresh_token)
-            .refresh(username)
-            .await?;
-        let client = Arc::new(client);
-
-        enum StreamState {
-            Init,
-            Next(Option<String>),
-        }
+    async fn to_bookmark_stream(&self, cursor: Option<String>) -> anyhow::Result<BookmarkStream> {
+        let client = Arc::new(authenticate().await?);
+        let settings = &settings::get_settings().reddit;
 
-        let stream = futures::stream::unfold(StreamState::Init, move |state| {
+        // the cursor is the `after` fullname of the next listing page
+        Ok(paged_stream(cursor, move |after| {
             let client = client.clone();
-
             async move {
-                let after = match &state {
-                    StreamState::Init => None,
-                    StreamState::Next(after) => after.as_ref().cloned(),
-                };
-                if after.is_none() && matches!(state, StreamState::Next(_)) {
-                    return None;
+                let resp = client
+                    .list_saved(after.as_deref())
+                    .await
+                    .context("Reddit list_saved failed")?;
+
+                if let Some(limit) = client.rate_limit() {
+                    let resets_in = limit.reset_at.saturating_duration_since(Instant::now());
+                    tracing::info!(
+                        "Reddit rate limit: {} requests left, {} used, resets in {:?}",
+                        limit.remaining,
+                        limit.used,
+                        resets_in
+                    );
+                    metrics::get_metrics().set_rate_limit(
+                        "reddit",
+                        limit.remaining,
+                        limit.used,
+                        resets_in,
+                    );
                 }
 
-                let resp = match client.list_saved(after.as_deref()).await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        tracing::error!("Reddit list_saved failed: {:#}", e);
-                        return None;
-                    }
-                };
-
-                let items = resp
-                    .posts
-                    .into_iter()
-                    .map(|post| BookmarkCreate {
-                        title: post.title,
-                        url: post.url,
-                        // Reddt does not provide timestamp for when the post was saved
-                        created_at: None,
-                    })
-                    .collect::<Vec<_>>();
-
-                Some((items, StreamState::Next(resp.after)))
+                Ok(BookmarkChunk {
+                    bookmarks: resp
+                        .posts
+                        .into_iter()
+                        .filter_map(|post| into_bookmark(post, settings))
+                        .collect(),
+                    cursor: resp.after,
+                })
             }
-        });
-
-        Ok(Box::pin(stream))
+        }))
     }
 
     fn is_activated(&self) -> bool {
@@ -106,4 +174,172 @@ impl super::Plugin for RedditSaves {
         let settings = settings::get_settings();
         settings.reddit.schedule.clone()
     }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        authenticate().await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::into_bookmark;
+    use crate::settings::{RedditLinks, RedditNsfw, RedditSettings};
+    use reddit_client::{SavedKind, SavedPost};
+
+    fn settings(links: RedditLinks, nsfw: RedditNsfw) -> RedditSettings {
+        RedditSettings {
+            clientid: None,
+            clientsecret: None,
+            refreshtoken: None,
+            username: None,
+            comments: true,
+            links,
+            nsfw,
+            nsfwlist: "Reddit NSFW".to_string(),
+            schedule: "@daily".to_string(),
+        }
+    }
+
+    fn link_post() -> SavedPost {
+        SavedPost {
+            kind: SavedKind::Post,
+            fullname: Some("t3_abc".to_string()),
+            title: "An article".to_string(),
+            url: "https://reddit.com/r/rust/comments/abc/an_article/".to_string(),
+            author: Some("alice".to_string()),
+            body: None,
+            link: Some("https://example.com/article".to_string()),
+            domain: Some("example.com".to_string()),
+            subreddit: Some("rust".to_string()),
+            created_utc: Some(1704164645.0),
+            over_18: false,
+            flair: Some("News".to_string()),
+            score: Some(42),
+        }
+    }
+
+    fn comment() -> SavedPost {
+        SavedPost {
+            kind: SavedKind::Comment,
+            fullname: Some("t1_def".to_string()),
+            title: "A post".to_string(),
+            url: "https://reddit.com/r/rust/comments/abc/a_post/def/".to_string(),
+            author: Some("bob".to_string()),
+            body: Some("Great point\n".to_string()),
+            link: None,
+            domain: None,
+            subreddit: Some("rust".to_string()),
+            created_utc: None,
+            over_18: false,
+            flair: None,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn test_comment_into_bookmark() {
+        let settings = settings(RedditLinks::Both, RedditNsfw::Tag);
+        let bookmark = into_bookmark(comment(), &settings).unwrap();
+        assert_eq!(bookmark.title, "A post");
+        assert_eq!(
+            bookmark.url,
+            "https://reddit.com/r/rust/comments/abc/a_post/def/"
+        );
+        assert_eq!(bookmark.note.as_deref(), Some("Great point\n\n— u/bob"));
+        assert_eq!(bookmark.source_id(), "t1_def");
+
+        let settings = RedditSettings {
+            comments: false,
+            ..settings
+        };
+        assert!(into_bookmark(comment(), &settings).is_none());
+    }
+
+    #[test]
+    fn test_link_post_into_bookmark() {
+        let bookmark = into_bookmark(
+            link_post(),
+            &settings(RedditLinks::Permalink, RedditNsfw::Tag),
+        )
+        .unwrap();
+        assert_eq!(
+            bookmark.url,
+            "https://reddit.com/r/rust/comments/abc/an_article/"
+        );
+        assert_eq!(bookmark.note, None);
+
+        let bookmark = into_bookmark(
+            link_post(),
+            &settings(RedditLinks::Article, RedditNsfw::Tag),
+        )
+        .unwrap();
+        assert_eq!(bookmark.url, "https://example.com/article");
+        assert_eq!(bookmark.note, None);
+
+        let bookmark =
+            into_bookmark(link_post(), &settings(RedditLinks::Both, RedditNsfw::Tag)).unwrap();
+        assert_eq!(bookmark.title, "An article");
+        assert_eq!(bookmark.url, "https://example.com/article");
+        assert_eq!(
+            bookmark.note.as_deref(),
+            Some("Discussion: https://reddit.com/r/rust/comments/abc/an_article/")
+        );
+    }
+
+    #[test]
+    fn test_source_id_does_not_depend_on_links() {
+        // switching the links mode must not sync every saved post again
+        for links in [
+            RedditLinks::Permalink,
+            RedditLinks::Article,
+            RedditLinks::Both,
+        ] {
+            let bookmark = into_bookmark(link_post(), &settings(links, RedditNsfw::Tag)).unwrap();
+            assert_eq!(bookmark.source_id(), "t3_abc");
+        }
+
+        // two posts of the same article are still different items
+        let other = SavedPost {
+            fullname: Some("t3_xyz".to_string()),
+            ..link_post()
+        };
+        let settings = settings(RedditLinks::Article, RedditNsfw::Tag);
+        let other = into_bookmark(other, &settings).unwrap();
+        let bookmark = into_bookmark(link_post(), &settings).unwrap();
+        assert_eq!(other.url, bookmark.url);
+        assert_ne!(other.source_id(), bookmark.source_id());
+    }
+
+    #[test]
+    fn test_post_metadata() {
+        let settings = settings(RedditLinks::Permalink, RedditNsfw::Tag);
+        let bookmark = into_bookmark(link_post(), &settings).unwrap();
+        assert_eq!(bookmark.source_id(), "t3_abc");
+        assert_eq!(bookmark.tags, vec!["r/rust", "News"]);
+        assert_eq!(
+            bookmark.created_at.unwrap().to_string(),
+            "2024-01-02T03:04:05.000Z"
+        );
+        assert_eq!(bookmark.list, None);
+    }
+
+    #[test]
+    fn test_nsfw_policy() {
+        let nsfw = || SavedPost {
+            over_18: true,
+            ..link_post()
+        };
+
+        let tagged = into_bookmark(nsfw(), &settings(RedditLinks::Permalink, RedditNsfw::Tag));
+        assert_eq!(tagged.unwrap().tags, vec!["r/rust", "News", "nsfw"]);
+
+        let listed =
+            into_bookmark(nsfw(), &settings(RedditLinks::Permalink, RedditNsfw::List)).unwrap();
+        assert_eq!(listed.list.as_deref(), Some("Reddit NSFW"));
+        assert_eq!(listed.tags, vec!["r/rust", "News"]);
+
+        assert!(
+            into_bookmark(nsfw(), &settings(RedditLinks::Permalink, RedditNsfw::Skip)).is_none()
+        );
+    }
 }
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemFailure {
+    pub url: String,
+    pub reason: String,
+}
+
+/// A change a dry run found it would make to Karakeep.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlannedAction {
+    CreateList {
+        list: String,
+    },
+    CreateBookmark {
+        list: String,
+        title: String,
+        url: String,
+    },
+    AddToList {
+        list: String,
+        bookmark_id: String,
+        url: String,
+    },
+    RemoveFromList {
+        list: String,
+        bookmark_id: String,
+    },
+}
+
+/// Outcome of a single `Plugin::sync` run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncReport {
+    pub plugin: String,
+    /// Unix timestamp of when the run started.
+    pub started_at: i64,
+    pub duration_ms: u64,
+    /// Bookmarks newly created in Karakeep.
+    pub created: u32,
+    /// Bookmarks that already existed in Karakeep.
+    pub existing: u32,
+    /// Bookmarks newly added to the plugin's list, whether created or existing.
+    pub added_to_list: u32,
+    /// Bookmarks taken out of a mirrored list because they left it at the
+    /// source.
+    pub removed_from_list: u32,
+    /// Items skipped because a previous run already synced them or because
+    /// they are waiting in the dead-letter queue.
+    pub skipped: u32,
+    /// Dead-letter queue items that were due and attempted again.
+    pub retried: u32,
+    pub failed: u32,
+    /// Failed items that used up their retry attempts and will not be retried.
+    pub dead_lettered: u32,
+    pub failures: Vec<ItemFailure>,
+    /// Whether the run stopped before the source stream was exhausted.
+    pub stopped_early: bool,
+    /// Why reading the source failed, if it did. The run then stops early and
+    /// counts as failed, unlike one stopped by a shutdown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_error: Option<String>,
+    /// Whether this was a dry run, in which case the counts above describe
+    /// what would have happened and `actions` lists the changes.
+    pub dry_run: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<PlannedAction>,
+}
+
+impl SyncReport {
+    pub fn new(plugin: &str) -> Self {
+        Self {
+            plugin: plugin.to_string(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record_failure(&mut self, url: &str, error: &anyhow::Error) {
+        self.failed += 1;
+        self.failures.push(ItemFailure {
+            url: url.to_string(),
+            reason: format!("{error:#}"),
+        });
+    }
+
+    pub fn finish(&mut self, duration: Duration) {
+        self.duration_ms = duration.as_millis() as u64;
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    pub fn record_source_error(&mut self, error: &anyhow::Error) {
+        self.stopped_early = true;
+        self.source_error = Some(format!("{error:#}"));
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed == 0 && !self.stopped_early
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "created={}, existing={}, added_to_list={}, removed_from_list={}, skipped={}, retried={}, failed={}, dead_lettered={}, stopped_early={}, duration={:.1}s",
+            self.created,
+            self.existing,
+            self.added_to_list,
+            self.removed_from_list,
+            self.skipped,
+            self.retried,
+            self.failed,
+            self.dead_lettered,
+            self.stopped_early,
+            self.duration().as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SyncReport;
+    use std::time::Duration;
+
+    #[test]
+    fn test_report_failures() {
+        let mut report = SyncReport::new("hn");
+        assert!(report.is_success());
+
+        report.record_failure("https://example.com", &anyhow::anyhow!("boom"));
+        assert!(!report.is_success());
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.failures[0].url, "https://example.com");
+        assert_eq!(report.failures[0].reason, "boom");
+    }
+
+    #[test]
+    fn test_report_source_error() {
+        let mut report = SyncReport::new("github");
+        report.record_source_error(&anyhow::anyhow!("GitHub returned 502"));
+        assert!(!report.is_success());
+        assert!(report.stopped_early);
+        assert_eq!(report.source_error.as_deref(), Some("GitHub returned 502"));
+    }
+
+    #[test]
+    fn test_report_display() {
+        let mut report = SyncReport::new("hn");
+        report.created = 2;
+        report.skipped = 3;
+        report.finish(Duration::from_millis(1500));
+
+        assert_eq!(
+            report.to_string(),
+            "created=2, existing=0, added_to_list=0, removed_from_list=0, skipped=3, retried=0, failed=0, dead_lettered=0, stopped_early=false, duration=1.5s"
+        );
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedMutexGuard, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics;
+use crate::plugin::{Plugin, SyncOptions};
+use crate::report::SyncReport;
+use crate::settings::Overlap;
+
+/// Owns the registered plugins and makes sure each one only has a single sync
+/// in flight, whether it was started by the scheduler or the control API.
+pub struct Runner {
+    plugins: Vec<Arc<dyn Plugin>>,
+    slots: Mutex<HashMap<&'static str, Arc<Slot>>>,
+    overlap: Overlap,
+    limit: Option<Arc<Semaphore>>,
+    cancel: CancellationToken,
+}
+
+/// Run lock of a single plugin. The lock is held for the whole sync, so a
+/// panicking or cancelled sync does not leave the plugin locked.
+#[derive(Default)]
+struct Slot {
+    lock: Arc<tokio::sync::Mutex<()>>,
+    queued: AtomicBool,
+}
+
+impl Slot {
+    /// Waits for the running sync to finish, keeping the plugin marked as
+    /// queued until then, even if the wait is cancelled.
+    async fn wait(&self) -> OwnedMutexGuard<()> {
+        struct Queued<'a>(&'a AtomicBool);
+        impl Drop for Queued<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::SeqCst);
+            }
+        }
+
+        let _queued = Queued(&self.queued);
+        self.lock.clone().lock_owned().await
+    }
+}
+
+enum Lock {
+    Acquired(OwnedMutexGuard<()>),
+    Queued(Arc<Slot>),
+    Skipped,
+}
+
+/// Outcome of asking the runner to start a sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Started,
+    /// Another sync is running, this one starts after it.
+    Queued,
+    /// Another sync is running and one is already queued (or queueing is
+    /// disabled), so nothing was started.
+    Skipped,
+}
+
+impl Runner {
+    pub fn new(plugins: Vec<Arc<dyn Plugin>>) -> Self {
+        Self {
+            plugins,
+            slots: Mutex::new(HashMap::new()),
+            overlap: Overlap::Skip,
+            limit: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    pub fn with_overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Limits how many plugins sync at the same time, 0 means no limit.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.limit = (concurrency > 0).then(|| Arc::new(Semaphore::new(concurrency)));
+        self
+    }
+
+    fn slot(&self, plugin: &'static str) -> Arc<Slot> {
+        self.slots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(plugin)
+            .or_default()
+            .clone()
+    }
+
+    pub fn plugins(&self) -> &[Arc<dyn Plugin>] {
+        &self.plugins
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<dyn Plugin>> {
+        self.plugins.iter().find(|p| p.name() == name).cloned()
+    }
+
+    pub fn is_running(&self, name: &str) -> bool {
+        let slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots
+            .get(name)
+            .is_some_and(|slot| slot.lock.try_lock().is_err())
+    }
+
+    /// Takes the run lock of `plugin` if it is free. Otherwise decides, based
+    /// on the overlap setting, whether the caller should wait for it.
+    fn try_lock(&self, plugin: &dyn Plugin) -> Lock {
+        if self.cancel.is_cancelled() {
+            tracing::info!(
+                "shutting down, not starting sync for list '{}'",
+                plugin.list_name()
+            );
+            return Lock::Skipped;
+        }
+
+        let slot = self.slot(plugin.name());
+        if let Ok(guard) = slot.lock.clone().try_lock_owned() {
+            return Lock::Acquired(guard);
+        }
+
+        if self.overlap == Overlap::Queue && !slot.queued.swap(true, Ordering::SeqCst) {
+            tracing::info!(
+                "sync for list '{}' is already running, queued to run after it",
+                plugin.list_name()
+            );
+            Lock::Queued(slot)
+        } else {
+            tracing::info!(
+                "sync for list '{}' is already running, skipping",
+                plugin.list_name()
+            );
+            Lock::Skipped
+        }
+    }
+
+    /// Syncs `plugin` and records the outcome, or returns `None` without doing
+    /// anything if the run was skipped because of one already in progress or
+    /// a shutdown.
+    pub async fn run(&self, plugin: &Arc<dyn Plugin>) -> Option<anyhow::Result<SyncReport>> {
+        let guard = match self.try_lock(plugin.as_ref()) {
+            Lock::Acquired(guard) => guard,
+            Lock::Queued(slot) => slot.wait().await,
+            Lock::Skipped => return None,
+        };
+        self.sync_locked(plugin.as_ref(), guard).await
+    }
+
+    /// Starts a sync of `plugin` in the background.
+    pub fn spawn(self: &Arc<Self>, plugin: Arc<dyn Plugin>) -> Trigger {
+        let lock = self.try_lock(plugin.as_ref());
+        let trigger = match &lock {
+            Lock::Acquired(_) => Trigger::Started,
+            Lock::Queued(_) => Trigger::Queued,
+            Lock::Skipped => return Trigger::Skipped,
+        };
+
+        let runner = self.clone();
+        tokio::spawn(async move {
+            let guard = match lock {
+                Lock::Acquired(guard) => guard,
+                Lock::Queued(slot) => slot.wait().await,
+                Lock::Skipped => unreachable!(),
+            };
+            // the outcome is already logged and counted by `sync_locked`
+            let _ = runner.sync_locked(plugin.as_ref(), guard).await;
+        });
+        trigger
+    }
+
+    async fn sync_locked(
+        &self,
+        plugin: &dyn Plugin,
+        _guard: OwnedMutexGuard<()>,
+    ) -> Option<anyhow::Result<SyncReport>> {
+        let _permit = match &self.limit {
+            Some(limit) => {
+                if limit.available_permits() == 0 {
+                    tracing::info!(
+                        "concurrency limit reached, sync for list '{}' waits for another to finish",
+                        plugin.list_name()
+                    );
+                }
+                tokio::select! {
+                    _ = self.cancel.cancelled() => None,
+                    // the semaphore is never closed
+                    permit = limit.clone().acquire_owned() => Some(permit.expect("semaphore closed")),
+                }
+            }
+            None => None,
+        };
+        // a queued run or one waiting for the concurrency limit may only get
+        // its turn after shutdown started
+        if self.cancel.is_cancelled() {
+            return None;
+        }
+
+        let options = SyncOptions {
+            cancel: self.cancel.clone(),
+            ..Default::default()
+        };
+        let result = plugin.sync(&options).await;
+        record_sync_result(plugin, &result);
+        Some(result)
+    }
+
+    /// Asks in-flight syncs to stop after their current item and waits up to
+    /// `grace` for them to finish. No new syncs are started afterwards.
+    /// Returns `false` if some syncs were still running when `grace` ran out.
+    pub async fn shutdown(&self, grace: Duration) -> bool {
+        self.cancel.cancel();
+
+        let slots = self
+            .slots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let finished = async {
+            for slot in slots {
+                drop(slot.lock.lock().await);
+            }
+        };
+
+        if tokio::time::timeout(grace, finished).await.is_ok() {
+            return true;
+        }
+        for plugin in &self.plugins {
+            if self.is_running(plugin.name()) {
+                tracing::warn!(
+                    "sync for list '{}' did not stop within {:?}",
+                    plugin.list_name(),
+                    grace
+                );
+            }
+        }
+        false
+    }
+}
+
+fn record_sync_result(plugin: &dyn Plugin, result: &anyhow::Result<SyncReport>) {
+    let list_name = plugin.list_name();
+    let metrics = metrics::get_metrics();
+    match result {
+        Ok(report) if report.is_success() => {
+            metrics.observe_report(report);
+            tracing::info!("sync succeeded for list '{}': {}", list_name, report);
+        }
+        Ok(report) if let Some(error) = &report.source_error => {
+            metrics.observe_report(report);
+            tracing::error!(
+                "sync failed to read the source of list '{}', the next run resumes from the last checkpoint: {} ({})",
+                list_name,
+                error,
+                report
+            );
+        }
+        Ok(report) if report.stopped_early && report.failed == 0 => {
+            metrics.observe_report(report);
+            tracing::info!("sync stopped early for list '{}': {}", list_name, report);
+        }
+        Ok(report) => {
+            metrics.observe_report(report);
+            tracing::warn!(
+                "sync finished with errors for list '{}': {}",
+                list_name,
+                report
+            );
+            for failure in &report.failures {
+                tracing::warn!("  failed: {} ({})", failure.url, failure.reason);
+            }
+        }
+        Err(e) => {
+            metrics.observe_run_error(plugin.name());
+            tracing::error!("sync failed for list '{}': {:#}", list_name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{Runner, Trigger};
+    use crate::plugin::{BookmarkStream, Plugin, SyncOptions};
+    use crate::report::SyncReport;
+    use crate::settings::Overlap;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    /// Plugin whose sync blocks until `release` is notified.
+    pub(crate) struct BlockingPlugin {
+        pub name: &'static str,
+        pub release: Notify,
+        pub started: AtomicBool,
+        pub runs: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Plugin for BlockingPlugin {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn list_name(&self) -> &'static str {
+            "Blocking"
+        }
+
+        async fn to_bookmark_stream(
+            &self,
+            _cursor: Option<String>,
+        ) -> anyhow::Result<BookmarkStream> {
+            Ok(Box::pin(futures::stream::empty()))
+        }
+
+        fn is_activated(&self) -> bool {
+            true
+        }
+
+        fn recurring_schedule(&self) -> String {
+            "@daily".to_string()
+        }
+
+        async fn check(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn sync(&self, options: &SyncOptions) -> anyhow::Result<SyncReport> {
+            self.started.store(true, Ordering::SeqCst);
+            let mut report = SyncReport::new(self.name());
+            tokio::select! {
+                _ = self.release.notified() => {}
+                _ = options.cancel.cancelled() => report.stopped_early = true,
+            }
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            Ok(report)
+        }
+    }
+
+    pub(crate) fn blocking_plugin() -> Arc<BlockingPlugin> {
+        blocking_plugin_named("blocking")
+    }
+
+    fn blocking_plugin_named(name: &'static str) -> Arc<BlockingPlugin> {
+        Arc::new(BlockingPlugin {
+            name,
+            release: Notify::new(),
+            started: AtomicBool::new(false),
+            runs: AtomicU32::new(0),
+        })
+    }
+
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        while !condition() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refuses_concurrent_runs() {
+        let plugin = blocking_plugin();
+        let runner = Arc::new(Runner::new(vec![plugin.clone()]));
+        let dyn_plugin = runner.find("blocking").unwrap();
+
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Started);
+        assert!(runner.is_running("blocking"));
+
+        // both another background run and a scheduled run are refused
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Skipped);
+        assert!(runner.run(&dyn_plugin).await.is_none());
+
+        plugin.release.notify_one();
+        wait_until(|| !runner.is_running("blocking")).await;
+        assert_eq!(plugin.runs.load(Ordering::SeqCst), 1);
+
+        // the lock is released once the run finishes
+        let run = tokio::spawn({
+            let runner = runner.clone();
+            async move { runner.run(&dyn_plugin).await }
+        });
+        wait_until(|| runner.is_running("blocking")).await;
+        plugin.release.notify_one();
+        assert!(run.await.unwrap().unwrap().is_ok());
+        assert_eq!(plugin.runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_queues_one_overlapping_run() {
+        let plugin = blocking_plugin();
+        let runner = Arc::new(Runner::new(vec![plugin.clone()]).with_overlap(Overlap::Queue));
+        let dyn_plugin = runner.find("blocking").unwrap();
+
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Started);
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Queued);
+        // only a single run is kept waiting
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Skipped);
+
+        plugin.release.notify_one();
+        wait_until(|| plugin.runs.load(Ordering::SeqCst) == 1).await;
+        plugin.release.notify_one();
+        wait_until(|| plugin.runs.load(Ordering::SeqCst) == 2).await;
+        wait_until(|| !runner.is_running("blocking")).await;
+
+        // the queue is free again once the queued run started
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Started);
+        plugin.release.notify_one();
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let first = blocking_plugin_named("first");
+        let second = blocking_plugin_named("second");
+        let runner = Arc::new(Runner::new(vec![first.clone(), second.clone()]).with_concurrency(1));
+
+        assert_eq!(
+            runner.spawn(runner.find("first").unwrap()),
+            Trigger::Started
+        );
+        assert_eq!(
+            runner.spawn(runner.find("second").unwrap()),
+            Trigger::Started
+        );
+        wait_until(|| first.started.load(Ordering::SeqCst)).await;
+
+        // the second sync waits for a free slot before it starts
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!second.started.load(Ordering::SeqCst));
+
+        first.release.notify_one();
+        wait_until(|| second.started.load(Ordering::SeqCst)).await;
+        second.release.notify_one();
+        wait_until(|| second.runs.load(Ordering::SeqCst) == 1).await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_running_syncs() {
+        let plugin = blocking_plugin();
+        let runner = Arc::new(Runner::new(vec![plugin.clone()]).with_overlap(Overlap::Queue));
+        let dyn_plugin = runner.find("blocking").unwrap();
+
+        let run = tokio::spawn({
+            let runner = runner.clone();
+            let dyn_plugin = dyn_plugin.clone();
+            async move { runner.run(&dyn_plugin).await }
+        });
+        wait_until(|| plugin.started.load(Ordering::SeqCst)).await;
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Queued);
+
+        assert!(runner.shutdown(Duration::from_secs(5)).await);
+        let report = run.await.unwrap().unwrap().unwrap();
+        assert!(report.stopped_early);
+
+        // neither the queued run nor new ones are started after shutdown
+        wait_until(|| !runner.is_running("blocking")).await;
+        assert_eq!(runner.spawn(dyn_plugin.clone()), Trigger::Skipped);
+        assert!(runner.run(&dyn_plugin).await.is_none());
+        assert_eq!(plugin.runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::Context;
 use config::Config;
 use serde::Deserialize;
 use std::sync::OnceLock;
@@ -7,6 +8,28 @@ use crate::settings;
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct GitHubSettings {
     pub token: Option<String>,
+    /// API base URL, `https://<host>/api/v3` for GitHub Enterprise Server.
+    pub url: String,
+    pub list: String,
+    /// Mirror every star list into a Karakeep list of the same name.
+    pub starlists: bool,
+    /// Karakeep list to nest the star lists under.
+    pub starlistsparent: Option<String>,
+    /// Also sync starred gists.
+    pub gists: bool,
+    pub gistslist: String,
+    /// Also sync watched repositories.
+    pub watched: bool,
+    pub watchedlist: String,
+    pub schedule: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct GiteaSettings {
+    pub token: Option<String>,
+    /// Instance URL, e.g. `https://codeberg.org`.
+    pub url: Option<String>,
+    pub list: String,
     pub schedule: String,
 }
 
@@ -22,12 +45,40 @@ pub(crate) struct KarakeepSettings {
     pub url: String,
 }
 
+/// What to bookmark for a saved Reddit post that links to another site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RedditLinks {
+    /// The Reddit thread.
+    Permalink,
+    /// The linked page.
+    Article,
+    /// The linked page, with the Reddit thread in the note.
+    Both,
+}
+
+/// What to do with saved Reddit items marked NSFW.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RedditNsfw {
+    Skip,
+    /// Sync them with an `nsfw` tag.
+    Tag,
+    /// Sync them to `nsfwlist` instead.
+    List,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct RedditSettings {
     pub clientid: Option<String>,
     pub clientsecret: Option<String>,
     pub refreshtoken: Option<String>,
     pub username: Option<String>,
+    /// Sync saved comments besides saved posts.
+    pub comments: bool,
+    pub links: RedditLinks,
+    pub nsfw: RedditNsfw,
+    pub nsfwlist: String,
     pub schedule: String,
 }
 
@@ -37,39 +88,106 @@ pub(crate) struct PinboardSettings {
     pub schedule: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct DataSettings {
+    pub dir: String,
+}
+
+/// What to do when a plugin's sync comes due while the previous one is still
+/// running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Overlap {
+    /// Drop the new run.
+    Skip,
+    /// Run it once the current one finishes. At most one run is kept waiting.
+    Queue,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SyncSettings {
+    pub maxattempts: u32,
+    pub overlap: Overlap,
+    /// Maximum number of plugins syncing at the same time, 0 for no limit.
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ShutdownSettings {
+    /// Seconds to wait for in-flight syncs to stop after a shutdown signal.
+    pub grace: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct AdminSettings {
+    /// Address for the admin HTTP server, which is disabled when unset.
+    pub listen: Option<String>,
+    /// Bearer token for the control endpoints, which are disabled when unset.
+    pub token: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct Settings {
+    pub admin: AdminSettings,
+    pub data: DataSettings,
+    pub sync: SyncSettings,
+    pub shutdown: ShutdownSettings,
     pub hn: HNSettings,
     pub karakeep: KarakeepSettings,
     pub reddit: RedditSettings,
     pub github: GitHubSettings,
+    pub gitea: GiteaSettings,
     pub pinboard: PinboardSettings,
 }
 
 impl Settings {
-    pub fn new() -> Self {
+    pub fn load() -> anyhow::Result<Self> {
         dotenvy::dotenv().ok();
 
         let config = Config::builder()
             .add_source(config::Environment::with_prefix("KS").separator("_"))
-            .set_override("hn.schedule", "@daily")
-            .unwrap()
-            .set_override("reddit.schedule", "@daily")
-            .unwrap()
-            .set_override("github.schedule", "@daily")
-            .unwrap()
-            .set_override("pinboard.schedule", "@daily")
-            .unwrap()
-            .build()
-            .unwrap();
+            .set_default("data.dir", "data")?
+            .set_default("sync.maxattempts", 5)?
+            .set_default("sync.overlap", "skip")?
+            .set_default("sync.concurrency", 0)?
+            .set_default("shutdown.grace", 8)?
+            .set_override("hn.schedule", "@daily")?
+            .set_default("reddit.comments", true)?
+            .set_default("reddit.links", "permalink")?
+            .set_default("reddit.nsfw", "tag")?
+            .set_default("reddit.nsfwlist", "Reddit NSFW")?
+            .set_override("reddit.schedule", "@daily")?
+            .set_default("github.url", github_client::API_URL)?
+            .set_default("github.list", "GitHub Starred")?
+            .set_default("github.starlists", false)?
+            .set_default("github.gists", false)?
+            .set_default("github.gistslist", "GitHub Gists")?
+            .set_default("github.watched", false)?
+            .set_default("github.watchedlist", "GitHub Watching")?
+            .set_override("github.schedule", "@daily")?
+            .set_default("gitea.list", "Gitea Starred")?
+            .set_default("gitea.schedule", "@daily")?
+            .set_override("pinboard.schedule", "@daily")?
+            .build()?;
 
         config
             .try_deserialize::<settings::Settings>()
-            .expect("Failed to deserialize settings")
+            .context("Failed to deserialize settings")
     }
 }
 
 static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Loads the settings on first use, reporting configuration errors instead of
+/// panicking like `get_settings` does.
+pub fn try_get_settings() -> anyhow::Result<&'static Settings> {
+    if let Some(settings) = SETTINGS.get() {
+        return Ok(settings);
+    }
+    let settings = Settings::load()?;
+    Ok(SETTINGS.get_or_init(|| settings))
+}
+
 pub fn get_settings() -> &'static Settings {
-    SETTINGS.get_or_init(Settings::new)
+    try_get_settings().expect("Failed to load settings")
 }
@@ -0,0 +1,19 @@
+use tokio::signal;
+
+/// Resolves on ctrl-c or, on unix, SIGTERM, which is what `docker stop` and
+/// Kubernetes send.
+pub async fn wait_for_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    signal::ctrl_c().await?;
+
+    Ok(())
+}
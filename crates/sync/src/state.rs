@@ -0,0 +1,540 @@
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use karakeep_client::BookmarkCreate;
+
+use crate::report::SyncReport;
+
+const STATE_FILE: &str = "karakeep-sync.db";
+
+/// Delay before the first retry of a failed item, doubled on every further attempt.
+const RETRY_BASE_SECS: i64 = 60 * 60;
+const RETRY_MAX_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// An item that failed to sync and is waiting in the dead-letter queue.
+#[derive(Debug)]
+pub struct FailedItem {
+    pub source_id: String,
+    pub bookmark: BookmarkCreate,
+    pub attempts: u32,
+}
+
+fn retry_delay(attempts: u32) -> i64 {
+    let exp = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE_SECS << exp).min(RETRY_MAX_SECS)
+}
+
+/// Durable record of what each plugin has already synced to Karakeep.
+///
+/// Every source item that made it into Karakeep is stored with the bookmark ID
+/// it maps to, so later runs can skip it without querying Karakeep again.
+pub struct StateStore {
+    conn: Mutex<Connection>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl StateStore {
+    pub fn open(data_dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(data_dir)
+            .with_context(|| format!("failed to create data dir {}", data_dir.display()))?;
+        let path = data_dir.join(STATE_FILE);
+        let conn = Connection::open(&path)
+            .with_context(|| format!("failed to open state store {}", path.display()))?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> anyhow::Result<Self> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS items (
+                plugin TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                bookmark_id TEXT NOT NULL,
+                synced_at INTEGER NOT NULL,
+                PRIMARY KEY (plugin, source_id)
+            );
+            CREATE TABLE IF NOT EXISTS plugins (
+                plugin TEXT PRIMARY KEY,
+                last_success_at INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS failed_items (
+                plugin TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                bookmark TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                last_error TEXT NOT NULL,
+                next_attempt_at INTEGER NOT NULL,
+                PRIMARY KEY (plugin, source_id)
+            );
+            CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                plugin TEXT NOT NULL,
+                started_at INTEGER NOT NULL,
+                report TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS checkpoints (
+                plugin TEXT PRIMARY KEY,
+                cursor TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            );",
+        )?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the Karakeep bookmark ID a source item was synced to, if any.
+    pub fn bookmark_id(&self, plugin: &str, source_id: &str) -> anyhow::Result<Option<String>> {
+        let id = self
+            .conn()
+            .query_row(
+                "SELECT bookmark_id FROM items WHERE plugin = ?1 AND source_id = ?2",
+                params![plugin, source_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(id)
+    }
+
+    /// Whether some item of `plugin` was synced to the Karakeep bookmark.
+    pub fn is_synced_bookmark(&self, plugin: &str, bookmark_id: &str) -> anyhow::Result<bool> {
+        let found = self
+            .conn()
+            .query_row(
+                "SELECT 1 FROM items WHERE plugin = ?1 AND bookmark_id = ?2 LIMIT 1",
+                params![plugin, bookmark_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    pub fn record_item(
+        &self,
+        plugin: &str,
+        source_id: &str,
+        bookmark_id: &str,
+    ) -> anyhow::Result<()> {
+        self.conn().execute(
+            "INSERT INTO items (plugin, source_id, bookmark_id, synced_at)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (plugin, source_id)
+            DO UPDATE SET bookmark_id = excluded.bookmark_id, synced_at = excluded.synced_at",
+            params![plugin, source_id, bookmark_id, now()],
+        )?;
+        Ok(())
+    }
+
+    /// Unix timestamp of the last run of `plugin` that completed without error.
+    pub fn last_success(&self, plugin: &str) -> anyhow::Result<Option<i64>> {
+        let ts = self
+            .conn()
+            .query_row(
+                "SELECT last_success_at FROM plugins WHERE plugin = ?1",
+                params![plugin],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(ts)
+    }
+
+    pub fn record_success(&self, plugin: &str) -> anyhow::Result<()> {
+        self.conn().execute(
+            "INSERT INTO plugins (plugin, last_success_at) VALUES (?1, ?2)
+            ON CONFLICT (plugin) DO UPDATE SET last_success_at = excluded.last_success_at",
+            params![plugin, now()],
+        )?;
+        Ok(())
+    }
+
+    /// Adds a failed item to the dead-letter queue, or bumps its attempt count
+    /// if it is already queued. Returns the number of attempts so far.
+    pub fn record_failure(
+        &self,
+        plugin: &str,
+        source_id: &str,
+        bookmark: &BookmarkCreate,
+        error: &str,
+    ) -> anyhow::Result<u32> {
+        let conn = self.conn();
+        let attempts: u32 = conn
+            .query_row(
+                "SELECT attempts FROM failed_items WHERE plugin = ?1 AND source_id = ?2",
+                params![plugin, source_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0)
+            + 1;
+
+        conn.execute(
+            "INSERT INTO failed_items
+                (plugin, source_id, bookmark, attempts, last_error, next_attempt_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (plugin, source_id) DO UPDATE SET
+                bookmark = excluded.bookmark,
+                attempts = excluded.attempts,
+                last_error = excluded.last_error,
+                next_attempt_at = excluded.next_attempt_at",
+            params![
+                plugin,
+                source_id,
+                serde_json::to_string(bookmark)?,
+                attempts,
+                error,
+                now() + retry_delay(attempts)
+            ],
+        )?;
+
+        Ok(attempts)
+    }
+
+    pub fn is_failed(&self, plugin: &str, source_id: &str) -> anyhow::Result<bool> {
+        let found = self
+            .conn()
+            .query_row(
+                "SELECT 1 FROM failed_items WHERE plugin = ?1 AND source_id = ?2",
+                params![plugin, source_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Queued items whose backoff has elapsed and that have not yet used up
+    /// `max_attempts`.
+    pub fn due_failures(&self, plugin: &str, max_attempts: u32) -> anyhow::Result<Vec<FailedItem>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT source_id, bookmark, attempts FROM failed_items
+            WHERE plugin = ?1 AND attempts < ?2 AND next_attempt_at <= ?3
+            ORDER BY next_attempt_at",
+        )?;
+        let rows = stmt.query_map(params![plugin, max_attempts, now()], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u32>(2)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (source_id, bookmark, attempts) = row?;
+            Ok(FailedItem {
+                source_id,
+                bookmark: serde_json::from_str(&bookmark)?,
+                attempts,
+            })
+        })
+        .collect()
+    }
+
+    /// Number of items of `plugin` currently in the dead-letter queue.
+    pub fn failed_count(&self, plugin: &str) -> anyhow::Result<u32> {
+        let count = self.conn().query_row(
+            "SELECT COUNT(*) FROM failed_items WHERE plugin = ?1",
+            params![plugin],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    /// Makes every queued item of `plugin` due again with a fresh set of
+    /// attempts, including dead-lettered ones. Returns how many were requeued.
+    pub fn requeue_failures(&self, plugin: &str) -> anyhow::Result<usize> {
+        let requeued = self.conn().execute(
+            "UPDATE failed_items SET attempts = 0, next_attempt_at = ?2 WHERE plugin = ?1",
+            params![plugin, now()],
+        )?;
+        Ok(requeued)
+    }
+
+    pub fn clear_failure(&self, plugin: &str, source_id: &str) -> anyhow::Result<()> {
+        self.conn().execute(
+            "DELETE FROM failed_items WHERE plugin = ?1 AND source_id = ?2",
+            params![plugin, source_id],
+        )?;
+        Ok(())
+    }
+
+    /// Cursor to resume an interrupted sync of `plugin` from, if any.
+    pub fn checkpoint(&self, plugin: &str) -> anyhow::Result<Option<String>> {
+        let cursor = self
+            .conn()
+            .query_row(
+                "SELECT cursor FROM checkpoints WHERE plugin = ?1",
+                params![plugin],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(cursor)
+    }
+
+    pub fn save_checkpoint(&self, plugin: &str, cursor: &str) -> anyhow::Result<()> {
+        self.conn().execute(
+            "INSERT INTO checkpoints (plugin, cursor, updated_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT (plugin) DO UPDATE SET
+                cursor = excluded.cursor,
+                updated_at = excluded.updated_at",
+            params![plugin, cursor, now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_checkpoint(&self, plugin: &str) -> anyhow::Result<()> {
+        self.conn()
+            .execute("DELETE FROM checkpoints WHERE plugin = ?1", params![plugin])?;
+        Ok(())
+    }
+
+    pub fn record_run(&self, report: &SyncReport) -> anyhow::Result<()> {
+        self.conn().execute(
+            "INSERT INTO runs (plugin, started_at, report) VALUES (?1, ?2, ?3)",
+            params![
+                report.plugin,
+                report.started_at,
+                serde_json::to_string(report)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Most recent run reports for `plugin`, newest first.
+    pub fn recent_runs(&self, plugin: &str, limit: usize) -> anyhow::Result<Vec<SyncReport>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT report FROM runs WHERE plugin = ?1 ORDER BY id DESC LIMIT ?2")?;
+        let rows = stmt.query_map(params![plugin, limit as i64], |row| row.get::<_, String>(0))?;
+
+        rows.map(|row| Ok(serde_json::from_str(&row?)?)).collect()
+    }
+}
+
+static STORE: OnceLock<StateStore> = OnceLock::new();
+
+pub fn try_get_store() -> anyhow::Result<&'static StateStore> {
+    if let Some(store) = STORE.get() {
+        return Ok(store);
+    }
+    let settings = &crate::settings::try_get_settings()?.data;
+    let store = StateStore::open(Path::new(&settings.dir))?;
+    Ok(STORE.get_or_init(|| store))
+}
+
+pub fn get_store() -> &'static StateStore {
+    try_get_store().expect("Failed to open state store")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RETRY_BASE_SECS, RETRY_MAX_SECS, StateStore, retry_delay};
+    use crate::report::SyncReport;
+    use karakeep_client::BookmarkCreate;
+
+    #[test]
+    fn test_record_and_lookup_items() {
+        let store = StateStore::open_in_memory().unwrap();
+
+        assert_eq!(
+            store.bookmark_id("hn", "https://example.com").unwrap(),
+            None
+        );
+
+        store
+            .record_item("hn", "https://example.com", "bookmark-1")
+            .unwrap();
+        assert_eq!(
+            store.bookmark_id("hn", "https://example.com").unwrap(),
+            Some("bookmark-1".to_string())
+        );
+
+        // items are tracked per plugin
+        assert_eq!(
+            store.bookmark_id("reddit", "https://example.com").unwrap(),
+            None
+        );
+
+        // re-recording an item updates the bookmark it maps to
+        store
+            .record_item("hn", "https://example.com", "bookmark-2")
+            .unwrap();
+        assert_eq!(
+            store.bookmark_id("hn", "https://example.com").unwrap(),
+            Some("bookmark-2".to_string())
+        );
+        assert!(store.is_synced_bookmark("hn", "bookmark-2").unwrap());
+        assert!(!store.is_synced_bookmark("hn", "bookmark-1").unwrap());
+        assert!(!store.is_synced_bookmark("reddit", "bookmark-2").unwrap());
+    }
+
+    #[test]
+    fn test_record_success() {
+        let store = StateStore::open_in_memory().unwrap();
+
+        assert_eq!(store.last_success("github").unwrap(), None);
+        store.record_success("github").unwrap();
+        assert!(store.last_success("github").unwrap().is_some());
+        assert_eq!(store.last_success("pinboard").unwrap(), None);
+    }
+
+    #[test]
+    fn test_record_runs() {
+        let store = StateStore::open_in_memory().unwrap();
+
+        for created in 1..=3 {
+            let mut report = SyncReport::new("hn");
+            report.created = created;
+            store.record_run(&report).unwrap();
+        }
+
+        let runs = store.recent_runs("hn", 2).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].created, 3);
+        assert_eq!(runs[1].created, 2);
+        assert!(store.recent_runs("github", 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let store = StateStore::open_in_memory().unwrap();
+
+        assert_eq!(store.checkpoint("github").unwrap(), None);
+        store.save_checkpoint("github", "?page=2").unwrap();
+        store.save_checkpoint("github", "?page=3").unwrap();
+        assert_eq!(
+            store.checkpoint("github").unwrap(),
+            Some("?page=3".to_string())
+        );
+        assert_eq!(store.checkpoint("reddit").unwrap(), None);
+
+        store.clear_checkpoint("github").unwrap();
+        assert_eq!(store.checkpoint("github").unwrap(), None);
+    }
+
+    fn bookmark(url: &str) -> BookmarkCreate {
+        BookmarkCreate {
+            title: "title".to_string(),
+            url: url.to_string(),
+            created_at: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_failed_items_backoff() {
+        let store = StateStore::open_in_memory().unwrap();
+        let item = bookmark("https://example.com");
+
+        assert!(!store.is_failed("hn", &item.url).unwrap());
+        assert_eq!(
+            store
+                .record_failure("hn", &item.url, &item, "boom")
+                .unwrap(),
+            1
+        );
+        assert!(store.is_failed("hn", &item.url).unwrap());
+
+        // not due until the backoff has elapsed
+        assert!(store.due_failures("hn", 5).unwrap().is_empty());
+
+        store
+            .conn()
+            .execute("UPDATE failed_items SET next_attempt_at = 0", [])
+            .unwrap();
+        let due = store.due_failures("hn", 5).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].bookmark.url, "https://example.com");
+        assert_eq!(due[0].attempts, 1);
+
+        assert_eq!(store.failed_count("hn").unwrap(), 1);
+        store.clear_failure("hn", &item.url).unwrap();
+        assert!(!store.is_failed("hn", &item.url).unwrap());
+        assert_eq!(store.failed_count("hn").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_failed_items_exhaust_attempts() {
+        let store = StateStore::open_in_memory().unwrap();
+        let item = bookmark("");
+
+        for attempt in 1..=3 {
+            assert_eq!(
+                store
+                    .record_failure("github", "", &item, "invalid url")
+                    .unwrap(),
+                attempt
+            );
+        }
+
+        store
+            .conn()
+            .execute("UPDATE failed_items SET next_attempt_at = 0", [])
+            .unwrap();
+        assert_eq!(store.due_failures("github", 4).unwrap().len(), 1);
+        // items that used up their attempts stay queued but are no longer retried
+        assert!(store.due_failures("github", 3).unwrap().is_empty());
+        assert!(store.is_failed("github", "").unwrap());
+
+        // until they are requeued
+        assert_eq!(store.requeue_failures("hn").unwrap(), 0);
+        assert_eq!(store.requeue_failures("github").unwrap(), 1);
+        let due = store.due_failures("github", 3).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 0);
+        assert_eq!(
+            store
+                .record_failure("github", "", &item, "invalid url")
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), RETRY_BASE_SECS);
+        assert_eq!(retry_delay(2), 2 * RETRY_BASE_SECS);
+        assert_eq!(retry_delay(3), 4 * RETRY_BASE_SECS);
+        assert_eq!(retry_delay(100), RETRY_MAX_SECS);
+    }
+
+    #[test]
+    fn test_open_persists_to_data_dir() {
+        let dir = std::env::temp_dir().join(format!("karakeep-sync-test-{}", std::process::id()));
+
+        {
+            let store = StateStore::open(&dir).unwrap();
+            store
+                .record_item("hn", "https://example.com", "b1")
+                .unwrap();
+        }
+
+        let store = StateStore::open(&dir).unwrap();
+        assert_eq!(
+            store.bookmark_id("hn", "https://example.com").unwrap(),
+            Some("b1".to_string())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}